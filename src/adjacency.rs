@@ -0,0 +1,123 @@
+use crate::graph::PageId;
+
+/// Tag of a list stored as plain varints
+const VARINT: u8 = 0;
+/// Tag of a list whose varints are compressed as one zstd frame
+const ZSTD: u8 = 1;
+
+/// Packs a link list into a BLOB for the `link_lists` table: a tag byte and then the difference
+/// of each id from the one before it (from 0 for the first), zigzag-encoded as LEB128 varints so
+/// the list keeps its order. Links to nearby pages take one or two bytes instead of a title.
+///
+/// With `zstd_level`, the varints are compressed too if that makes them smaller, which pays off
+/// for long lists. [`decode`] reads either.
+pub fn encode(ids: &[PageId], zstd_level: Option<i32>) -> Vec<u8> {
+    let mut varints = Vec::with_capacity(ids.len() * 2);
+    let mut previous = 0i64;
+    for id in ids {
+        let delta = *id as i64 - previous;
+        previous = *id as i64;
+
+        let mut zigzag = ((delta << 1) ^ (delta >> 63)) as u64;
+        while zigzag >= 0x80 {
+            varints.push(zigzag as u8 | 0x80);
+            zigzag >>= 7;
+        }
+        varints.push(zigzag as u8);
+    }
+
+    if let Some(level) = zstd_level {
+        let compressed = zstd::bulk::compress(&varints, level).unwrap();
+        if compressed.len() < varints.len() {
+            let mut encoded = Vec::with_capacity(compressed.len() + 1);
+            encoded.push(ZSTD);
+            encoded.extend(compressed);
+            return encoded;
+        }
+    }
+
+    let mut encoded = Vec::with_capacity(varints.len() + 1);
+    encoded.push(VARINT);
+    encoded.extend(varints);
+    encoded
+}
+
+/// Unpacks a list written by [`encode`]
+pub fn decode(encoded: &[u8]) -> Vec<PageId> {
+    let Some((tag, data)) = encoded.split_first() else { return Vec::new(); };
+    match *tag {
+        VARINT => decode_varints(data),
+        ZSTD => decode_varints(&zstd::stream::decode_all(data).unwrap()),
+        tag => panic!("Unknown link list encoding {}", tag)
+    }
+}
+
+fn decode_varints(data: &[u8]) -> Vec<PageId> {
+    let mut ids = Vec::with_capacity(data.len());
+    let mut previous = 0i64;
+    let mut value = 0u64;
+    let mut shift = 0;
+    for byte in data {
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 != 0 {
+            shift += 7;
+            continue;
+        }
+
+        let delta = (value >> 1) as i64 ^ -((value & 1) as i64);
+        previous += delta;
+        ids.push(previous as PageId);
+        value = 0;
+        shift = 0;
+    }
+    ids
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lists() -> Vec<Vec<PageId>> {
+        let long: Vec<PageId> = (0..5_000).map(|i| i * 3 + 100).collect();
+        vec![
+            vec![],
+            vec![0],
+            vec![PageId::MAX],
+            vec![5, 6, 7, 1_000_000, 1_000_001],
+            vec![900, 12, 800, 3, PageId::MAX, 0],
+            long.iter().rev().copied().collect(),
+            long,
+        ]
+    }
+
+    #[test]
+    fn round_trips_as_varints() {
+        for ids in lists() {
+            let encoded = encode(&ids, None);
+            assert_eq!(encoded[0], VARINT);
+            assert_eq!(decode(&encoded), ids);
+        }
+    }
+
+    #[test]
+    fn round_trips_with_zstd() {
+        for ids in lists() {
+            assert_eq!(decode(&encode(&ids, Some(3))), ids);
+        }
+
+        // Evenly spaced ids compress well, a single small one doesn't
+        let long: Vec<PageId> = (0..5_000).map(|i| i * 3 + 100).collect();
+        assert_eq!(encode(&long, Some(3))[0], ZSTD);
+        assert_eq!(encode(&[1], Some(3))[0], VARINT);
+    }
+
+    #[test]
+    fn nearby_ids_take_one_byte() {
+        assert_eq!(encode(&[10, 11, 9, 12], None).len(), 5);
+    }
+
+    #[test]
+    fn empty_blob_is_an_empty_list() {
+        assert!(decode(&[]).is_empty());
+    }
+}
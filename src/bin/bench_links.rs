@@ -0,0 +1,107 @@
+use std::{env, fs};
+use std::time::Instant;
+use num_format::{Locale, ToFormattedString};
+use rusqlite::{Connection, OptionalExtension};
+use wiki_3::adjacency;
+use wiki_3::graph::{LinkGraph, PageId, SqliteGraph};
+use wiki_3::meta;
+
+/// Compares ways of storing the links of a finished database: the `<|>`-joined titles
+/// `page_references` used, the `links` rows, and `link_lists` BLOBs with and without zstd. Each
+/// layout is written to its own database next to the original so the file sizes compare, then
+/// the same random pages are looked up in each. A lookup ends with the ids of the linked pages,
+/// so the titles of the text layout are looked up again as the search on it had to.
+fn main() {
+    let mut args = env::args().skip(1);
+    let db_path = args.next().unwrap_or("completed-table.db".to_string());
+    let lookups: usize = args.next().map_or(10_000, |n| n.parse().unwrap());
+
+    let source = SqliteGraph::new(meta::open_with_link_rows(&db_path).unwrap()).unwrap();
+    let sample = random_ids(source.len(), lookups);
+    let titles: Vec<String> = sample.iter().map(|id| source.title(*id)).collect();
+
+    println!("{:<14} {:>16} {:>14} {:>12}", "layout", "bytes", "lookup", "links read");
+    for layout in ["text", "rows", "varint", "varint+zstd"] {
+        let path = format!("{}.bench-{}", db_path, layout);
+        let _ = fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        write_layout(&conn, &source, layout);
+        conn.execute("VACUUM", ()).unwrap();
+        let bytes = fs::metadata(&path).unwrap().len();
+
+        let start = Instant::now();
+        let mut links = 0;
+        for (id, title) in sample.iter().zip(&titles) {
+            links += lookup(&conn, layout, title, *id);
+        }
+        let per_lookup = start.elapsed() / sample.len().max(1) as u32;
+        println!("{:<14} {:>16} {:>14} {:>12}", layout, bytes.to_formatted_string(&Locale::en), format!("{:?}", per_lookup), links.to_formatted_string(&Locale::en));
+
+        drop(conn);
+        fs::remove_file(&path).unwrap();
+    }
+}
+
+/// A fixed pseudo-random sample, so runs compare
+fn random_ids(pages: usize, count: usize) -> Vec<PageId> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    (0..count).map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % pages.max(1) as u64) as PageId
+    }).collect()
+}
+
+fn write_layout(conn: &Connection, source: &SqliteGraph, layout: &str) {
+    conn.execute("BEGIN", ()).unwrap();
+    match layout {
+        "text" => conn.execute("CREATE TABLE page_references (title TEXT PRIMARY KEY, links TEXT)", ()),
+        "rows" => conn.execute("CREATE TABLE links (src_id INTEGER, dst_id INTEGER, ordinal INTEGER, PRIMARY KEY (src_id, ordinal)) WITHOUT ROWID", ()),
+        _ => conn.execute("CREATE TABLE link_lists (id INTEGER PRIMARY KEY, links BLOB)", ())
+    }.unwrap();
+
+    for id in 0..source.len() as PageId {
+        let links: Vec<PageId> = source.links(id).collect();
+        match layout {
+            "text" => {
+                let titles: Vec<String> = links.iter().map(|link| source.title(*link)).collect();
+                conn.execute("INSERT INTO page_references VALUES (?, ?)", (source.title(id), titles.join("<|>"))).unwrap();
+            }
+            "rows" => {
+                for (ordinal, link) in links.iter().enumerate() {
+                    conn.execute("INSERT INTO links VALUES (?, ?, ?)", (id, link, ordinal)).unwrap();
+                }
+            }
+            _ if links.is_empty() => {}
+            _ => {
+                let zstd_level = (layout == "varint+zstd").then_some(zstd::DEFAULT_COMPRESSION_LEVEL);
+                conn.execute("INSERT INTO link_lists VALUES (?, ?)", (id, adjacency::encode(&links, zstd_level))).unwrap();
+            }
+        }
+    }
+    conn.execute("COMMIT", ()).unwrap();
+}
+
+/// Reads one page's links the way the layout is searched, returning how many there were
+fn lookup(conn: &Connection, layout: &str, title: &str, id: PageId) -> usize {
+    match layout {
+        "text" => {
+            let links: String = conn.prepare_cached("SELECT links FROM page_references WHERE title = ?").unwrap()
+                .query_row((title,), |row| row.get(0))
+                .unwrap();
+            let mut resolve = conn.prepare_cached("SELECT rowid - 1 FROM page_references WHERE title = ?").unwrap();
+            links.split("<|>")
+                .filter(|link| !link.is_empty())
+                .filter_map(|link| resolve.query_row((link,), |row| row.get::<_, PageId>(0)).optional().unwrap())
+                .count()
+        }
+        "rows" => conn.prepare_cached("SELECT dst_id FROM links WHERE src_id = ? ORDER BY ordinal").unwrap()
+            .query_map((id,), |row| row.get::<_, PageId>(0)).unwrap()
+            .count(),
+        _ => conn.prepare_cached("SELECT links FROM link_lists WHERE id = ?").unwrap()
+            .query_row((id,), |row| row.get::<_, Vec<u8>>(0))
+            .optional().unwrap()
+            .map_or(0, |encoded| adjacency::decode(&encoded).len())
+    }
+}
@@ -0,0 +1,224 @@
+use std::env;
+use std::process;
+use num_format::{Locale, ToFormattedString};
+use rusqlite::Connection;
+use wiki_3::meta::{self, has_table};
+use wiki_3::schema::{REDIRECT_BROKEN, REDIRECT_LOOP};
+
+const USAGE: &str = "Usage: check [--db <path>] [--examples <n>] [--max-red-links <n>] [--max-broken-redirects <n>] \
+    [--max-redirect-loops <n>] [--max-duplicates <n>] [--max-empty-pages <n>] [--max-errors <n>]";
+
+/// How many links of each version of a duplicate page are shown
+const DUPLICATE_LINKS_SHOWN: usize = 5;
+
+/// The thresholds, in the order the checks are reported
+const CHECKS: [(&str, &str); 6] = [
+    ("--max-red-links", "red links"),
+    ("--max-broken-redirects", "broken redirects"),
+    ("--max-redirect-loops", "looping redirects"),
+    ("--max-duplicates", "duplicate titles"),
+    ("--max-empty-pages", "pages without links"),
+    ("--max-errors", "failed inserts"),
+];
+
+/// Reports problems in a finished database and exits with status 1 if any count is over its
+/// `--max-*` threshold. Without thresholds it only reports.
+fn main() {
+    let mut db_path = "completed-table.db".to_string();
+    let mut examples = 10;
+    let mut limits: [Option<u64>; CHECKS.len()] = [None; CHECKS.len()];
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--db" => db_path = args.next().unwrap(),
+            "--examples" => examples = args.next().unwrap().parse().unwrap(),
+            _ => match CHECKS.iter().position(|(flag, _)| *flag == arg) {
+                Some(i) => limits[i] = Some(args.next().unwrap().parse().unwrap()),
+                None => {
+                    println!("{}", USAGE);
+                    process::exit(2);
+                }
+            }
+        }
+    }
+
+    let conn = meta::open_with_link_rows(&db_path).unwrap();
+    let counts = [
+        red_links(&conn, examples),
+        broken_redirects(&conn, examples),
+        redirect_loops(&conn, examples),
+        duplicate_titles(&conn, examples),
+        empty_pages(&conn, examples),
+        errors_by_cause(&conn),
+    ];
+
+    println!();
+    let mut failed = false;
+    for (((_, name), count), limit) in CHECKS.iter().zip(counts).zip(limits) {
+        match limit {
+            Some(limit) if count > limit => {
+                println!("FAILED: {} {} (limit {})", count.to_formatted_string(&Locale::en), name, limit.to_formatted_string(&Locale::en));
+                failed = true;
+            }
+            Some(limit) => println!("ok: {} {} (limit {})", count.to_formatted_string(&Locale::en), name, limit.to_formatted_string(&Locale::en)),
+            None => println!("{} {}", count.to_formatted_string(&Locale::en), name)
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+fn count(conn: &Connection, query: &str) -> u64 {
+    conn.query_row(query, (), |row| row.get(0)).unwrap()
+}
+
+/// The rows of a query with a single text column
+fn strings(conn: &Connection, query: &str, params: impl rusqlite::Params) -> Vec<String> {
+    conn.prepare(query).unwrap()
+        .query_map(params, |row| row.get(0)).unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn heading(title: &str, count: u64) {
+    println!("\n== {} ({}) ==", title, count.to_formatted_string(&Locale::en));
+}
+
+fn red_links(conn: &Connection, examples: usize) -> u64 {
+    let red = count(conn, "SELECT count(*) FROM unresolved_links");
+    let total = red + count(conn, "SELECT count(*) FROM links");
+    heading("Red links", red);
+    println!(
+        "{:.2}% of links, to {} different titles",
+        red as f64 * 100.0 / total.max(1) as f64,
+        count(conn, "SELECT count(DISTINCT target) FROM unresolved_links").to_formatted_string(&Locale::en)
+    );
+
+    let mut statement = conn.prepare(
+        "SELECT target, count(*) AS links FROM unresolved_links GROUP BY target ORDER BY links DESC, target LIMIT ?"
+    ).unwrap();
+    let mut rows = statement.query((examples,)).unwrap();
+    while let Some(row) = rows.next().unwrap() {
+        println!("  {} ({} links)", row.get::<_, String>(0).unwrap(), row.get::<_, u64>(1).unwrap());
+    }
+    red
+}
+
+fn broken_redirects(conn: &Connection, examples: usize) -> u64 {
+    let broken = conn.query_row("SELECT count(*) FROM pages WHERE redirect_status = ?", (REDIRECT_BROKEN,), |row| row.get(0)).unwrap();
+    heading("Broken redirects", broken);
+    // A redirect to a missing page has an unresolved link, one to a broken redirect a resolved one
+    for line in strings(
+        conn,
+        "SELECT pages.title || ' -> ' || coalesce(unresolved_links.target, target.title, '(no target)') FROM pages
+         LEFT JOIN unresolved_links ON unresolved_links.src_id = pages.id
+         LEFT JOIN links ON links.src_id = pages.id
+         LEFT JOIN pages AS target ON target.id = links.dst_id
+         WHERE pages.redirect_status = ? ORDER BY pages.id LIMIT ?",
+        (REDIRECT_BROKEN, examples)
+    ) {
+        println!("  {}", line);
+    }
+    broken
+}
+
+fn redirect_loops(conn: &Connection, examples: usize) -> u64 {
+    let loops = conn.query_row("SELECT count(*) FROM pages WHERE redirect_status = ?", (REDIRECT_LOOP,), |row| row.get(0)).unwrap();
+    heading("Looping redirects", loops);
+    for chain in strings(conn, "SELECT chain FROM redirect_reports WHERE kind = 'loop' ORDER BY id LIMIT ?", (examples,)) {
+        println!("  {}", chain);
+    }
+    loops
+}
+
+/// Errors from titles that were already taken. Databases built before causes were recorded
+/// count every error whose title has a page.
+const DUPLICATE_CONDITION: &str =
+    "(page_reference_errors.cause LIKE 'UNIQUE constraint failed%'
+      OR (page_reference_errors.cause IS NULL AND page_reference_errors.title IN (SELECT title FROM pages)))";
+
+fn duplicate_titles(conn: &Connection, examples: usize) -> u64 {
+    if !has_table(conn, "page_reference_errors") {
+        heading("Duplicate titles", 0);
+        return 0;
+    }
+
+    let duplicates = count(conn, &format!("SELECT count(*) FROM page_reference_errors WHERE {}", DUPLICATE_CONDITION));
+    heading("Duplicate titles", duplicates);
+
+    let mut statement = conn.prepare(&format!(
+        "SELECT page_reference_errors.title, page_reference_errors.links, page_reference_errors.is_redirect, pages.id, pages.is_redirect
+         FROM page_reference_errors JOIN pages ON pages.title = page_reference_errors.title
+         WHERE {} ORDER BY pages.id LIMIT ?",
+        DUPLICATE_CONDITION
+    )).unwrap();
+    let mut rows = statement.query((examples,)).unwrap();
+    while let Some(row) = rows.next().unwrap() {
+        let title: String = row.get(0).unwrap();
+        let dropped_links: Option<String> = row.get(1).unwrap();
+        let dropped_links: Vec<String> = dropped_links.unwrap_or_default()
+            .split("<|>").filter(|link| !link.is_empty()).map(|link| link.to_string()).collect();
+        let id: u32 = row.get(3).unwrap();
+
+        let kept_links = strings(
+            conn,
+            "SELECT title FROM (
+                SELECT links.ordinal, pages.title FROM links JOIN pages ON pages.id = links.dst_id WHERE links.src_id = ?1
+                UNION ALL
+                SELECT ordinal, target FROM unresolved_links WHERE src_id = ?1
+             ) ORDER BY ordinal",
+            (id,)
+        );
+
+        println!("  {}", title);
+        println!("    kept:    {}", describe_version(row.get(4).unwrap(), &kept_links));
+        println!("    dropped: {}", describe_version(row.get(2).unwrap(), &dropped_links));
+    }
+    duplicates
+}
+
+fn describe_version(is_redirect: bool, links: &[String]) -> String {
+    let kind = if is_redirect { "redirect" } else { "page" };
+    let shown = links.iter().take(DUPLICATE_LINKS_SHOWN).map(|link| link.as_str()).collect::<Vec<_>>().join(", ");
+    match links.len() {
+        0 => format!("{} without links", kind),
+        n if n > DUPLICATE_LINKS_SHOWN => format!("{} with {} links: {} and {} more", kind, n, shown, n - DUPLICATE_LINKS_SHOWN),
+        n => format!("{} with {} links: {}", kind, n, shown)
+    }
+}
+
+fn empty_pages(conn: &Connection, examples: usize) -> u64 {
+    const EMPTY: &str = "FROM pages WHERE NOT is_redirect
+        AND NOT EXISTS (SELECT 1 FROM links WHERE links.src_id = pages.id)
+        AND NOT EXISTS (SELECT 1 FROM unresolved_links WHERE unresolved_links.src_id = pages.id)";
+
+    let empty = count(conn, &format!("SELECT count(*) {}", EMPTY));
+    heading("Pages without links", empty);
+    for title in strings(conn, &format!("SELECT title {} ORDER BY id LIMIT ?", EMPTY), (examples,)) {
+        println!("  {}", title);
+    }
+    empty
+}
+
+fn errors_by_cause(conn: &Connection) -> u64 {
+    if !has_table(conn, "page_reference_errors") {
+        heading("Failed inserts", 0);
+        return 0;
+    }
+
+    let errors = count(conn, "SELECT count(*) FROM page_reference_errors");
+    heading("Failed inserts", errors);
+    let mut statement = conn.prepare(
+        "SELECT coalesce(cause, CASE WHEN title IN (SELECT title FROM pages) THEN 'duplicate title (cause not recorded)'
+                                     ELSE 'cause not recorded' END) AS grouped,
+                count(*) AS errors
+         FROM page_reference_errors GROUP BY grouped ORDER BY errors DESC"
+    ).unwrap();
+    let mut rows = statement.query(()).unwrap();
+    while let Some(row) = rows.next().unwrap() {
+        println!("  {}: {}", row.get::<_, String>(0).unwrap(), row.get::<_, u64>(1).unwrap().to_formatted_string(&Locale::en));
+    }
+    errors
+}
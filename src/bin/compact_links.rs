@@ -0,0 +1,161 @@
+use std::{env, fs};
+use std::time::Instant;
+use hhmmss::Hhmmss;
+use num_format::{Locale, ToFormattedString};
+use rusqlite::Connection;
+use wiki_3::adjacency;
+use wiki_3::graph::PageId;
+use wiki_3::meta::{self, has_link_lists, has_link_rows, BOTH_STORAGE, COMPACT_STORAGE};
+use wiki_3::schema::{build_backlinks, create_links_table};
+
+const USAGE: &str = "Usage: compact_links [db] [--zstd [level]] [--keep-rows] | compact_links [db] --expand";
+
+/// Packs the `links` and `backlinks` tables into one `link_lists(id, links, backlinks)` row per
+/// page with [`adjacency::encode`], then drops the tables and vacuums. The search reads either
+/// layout, tools that query the tables directly need `--expand` to put them back.
+fn main() {
+    let mut db_path = "completed-table.db".to_string();
+    let mut zstd_level = None;
+    let mut keep_rows = false;
+    let mut expand = false;
+
+    let mut args = env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--zstd" => {
+                zstd_level = Some(args.next_if(|level| level.parse::<i32>().is_ok()).map_or(zstd::DEFAULT_COMPRESSION_LEVEL, |level| level.parse().unwrap()));
+            }
+            "--keep-rows" => keep_rows = true,
+            "--expand" => expand = true,
+            _ if arg.starts_with("--") => {
+                println!("{}", USAGE);
+                return;
+            }
+            _ => db_path = arg
+        }
+    }
+
+    let start = Instant::now();
+    let size_before = fs::metadata(&db_path).unwrap().len();
+    let conn = meta::open(&db_path).unwrap();
+
+    if expand {
+        if !has_link_lists(&conn) {
+            println!("'{}' has no link_lists to expand", db_path);
+            return;
+        }
+        let links = expand_link_lists(&conn);
+        println!("Expanded {} links in {}", links.to_formatted_string(&Locale::en), start.elapsed().hhmmss());
+    } else {
+        if !has_link_rows(&conn) {
+            println!("'{}' is already compact", db_path);
+            return;
+        }
+        let pages = pack_link_lists(&conn, zstd_level, keep_rows);
+        println!("Packed the links of {} pages in {}", pages.to_formatted_string(&Locale::en), start.elapsed().hhmmss());
+    }
+
+    println!("Vacuuming");
+    conn.execute("VACUUM", ()).unwrap();
+    println!(
+        "'{}' went from {} to {} bytes in {}",
+        db_path,
+        size_before.to_formatted_string(&Locale::en),
+        fs::metadata(&db_path).unwrap().len().to_formatted_string(&Locale::en),
+        start.elapsed().hhmmss()
+    );
+}
+
+/// Returns the number of pages with links or backlinks
+fn pack_link_lists(conn: &Connection, zstd_level: Option<i32>, keep_rows: bool) -> usize {
+    conn.execute_batch(
+        "BEGIN;
+         DROP TABLE IF EXISTS link_lists;
+         CREATE TABLE link_lists (
+            id INTEGER PRIMARY KEY,
+            links BLOB,
+            backlinks BLOB
+         );"
+    ).unwrap();
+
+    let mut insert = conn.prepare(
+        "INSERT INTO link_lists (id, links) VALUES (?1, ?2) ON CONFLICT (id) DO UPDATE SET links = ?2"
+    ).unwrap();
+    for_each_list(conn, "SELECT src_id, dst_id FROM links ORDER BY src_id, ordinal", |id, links| {
+        insert.execute((id, adjacency::encode(links, zstd_level))).unwrap();
+    });
+    let mut insert = conn.prepare(
+        "INSERT INTO link_lists (id, backlinks) VALUES (?1, ?2) ON CONFLICT (id) DO UPDATE SET backlinks = ?2"
+    ).unwrap();
+    for_each_list(conn, "SELECT dst_id, src_id FROM backlinks ORDER BY dst_id, src_id", |id, backlinks| {
+        insert.execute((id, adjacency::encode(backlinks, zstd_level))).unwrap();
+    });
+    drop(insert);
+
+    if keep_rows {
+        meta::set(conn, "link_storage", BOTH_STORAGE);
+    } else {
+        conn.execute_batch("DROP TABLE links; DROP TABLE backlinks;").unwrap();
+        meta::set(conn, "link_storage", COMPACT_STORAGE);
+    }
+    meta::set(conn, "link_list_encoding", if zstd_level.is_some() { "varint+zstd" } else { "varint" });
+    conn.execute("COMMIT", ()).unwrap();
+
+    conn.query_row("SELECT count(*) FROM link_lists", (), |row| row.get(0)).unwrap()
+}
+
+/// Calls `consume` with each id and the ids paired with it by a query ordered by the first column
+fn for_each_list(conn: &Connection, query: &str, mut consume: impl FnMut(PageId, &[PageId])) {
+    let mut statement = conn.prepare(query).unwrap();
+    let mut rows = statement.query(()).unwrap();
+    let mut current = None;
+    let mut list = Vec::new();
+    while let Some(row) = rows.next().unwrap() {
+        let id: PageId = row.get(0).unwrap();
+        if current.is_some_and(|current| current != id) {
+            consume(current.unwrap(), &list);
+            list.clear();
+        }
+        current = Some(id);
+        list.push(row.get(1).unwrap());
+    }
+    if let Some(id) = current {
+        consume(id, &list);
+    }
+}
+
+/// Rebuilds `links` and `backlinks` from `link_lists`. Returns the number of links.
+fn expand_link_lists(conn: &Connection) -> usize {
+    conn.execute_batch("BEGIN; DROP TABLE IF EXISTS links;").unwrap();
+    create_links_table(conn).unwrap();
+
+    let mut insert = conn.prepare("INSERT INTO links VALUES (?, ?, ?)").unwrap();
+    let mut unresolved_ordinals = conn.prepare("SELECT ordinal FROM unresolved_links WHERE src_id = ? ORDER BY ordinal").unwrap();
+    let mut statement = conn.prepare("SELECT id, links FROM link_lists WHERE links IS NOT NULL").unwrap();
+    let mut rows = statement.query(()).unwrap();
+    let mut count = 0;
+    while let Some(row) = rows.next().unwrap() {
+        let src_id: PageId = row.get(0).unwrap();
+        let links = adjacency::decode(row.get_ref(1).unwrap().as_blob().unwrap());
+
+        // Ordinals count resolved and unresolved links together, so the resolved ones take the
+        // ordinals the unresolved ones left free
+        let taken: Vec<usize> = unresolved_ordinals.query_map((src_id,), |row| row.get(0)).unwrap()
+            .collect::<Result<_, _>>().unwrap();
+        let ordinals = (0..).filter(|ordinal| !taken.contains(ordinal));
+        for (dst_id, ordinal) in links.iter().zip(ordinals) {
+            insert.execute((src_id, dst_id, ordinal)).unwrap();
+            count += 1;
+        }
+    }
+    drop(rows);
+    drop(statement);
+    drop(insert);
+    drop(unresolved_ordinals);
+
+    build_backlinks(conn).unwrap();
+    conn.execute("DROP TABLE link_lists", ()).unwrap();
+    conn.execute("DELETE FROM meta WHERE key IN ('link_storage', 'link_list_encoding')", ()).unwrap();
+    conn.execute("COMMIT", ()).unwrap();
+    count
+}
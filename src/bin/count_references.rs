@@ -1,12 +1,19 @@
 use std::time::Instant;
 use hhmmss::Hhmmss;
-use rusqlite::Connection;
+use num_format::{Locale, ToFormattedString};
+use wiki_3::meta;
 
-const TOTAL_ARTICLES: u32 = 23_100_000;
+const UPDATE_BATCH: usize = 100_000;
 
+/// Counts the links to every page in one pass over `links` and stores them in two columns:
+/// - `reference_count`: links straight to the page
+/// - `reference_count_with_redirects`: links that end up on the page, counting a link to a
+///   redirect as a link to its resolved target. Redirects themselves get 0 unless broken or looping.
+///
+/// The links redirects have to their targets aren't references, so they aren't counted.
 fn main() {
     println!("Opening connection");
-    let conn = Connection::open("reference-count-table.db").unwrap();
+    let conn = meta::open_with_link_rows("reference-count-table.db").unwrap();
     println!("Configuring connection");
     conn.execute_batch(
         "PRAGMA journal_mode = OFF;
@@ -14,63 +21,74 @@ fn main() {
               PRAGMA locking_mode = EXCLUSIVE;
               PRAGMA temp_store = MEMORY;",
     ).unwrap();
-    println!("Dropping column");
-    if let Err(e) = conn.execute("ALTER TABLE page_references DROP COLUMN reference_count", ()) {
-        println!("Drop column failed with error {:?}", e);
+    for column in ["reference_count", "reference_count_with_redirects"] {
+        println!("Replacing column {}", column);
+        if let Err(e) = conn.execute(&format!("ALTER TABLE pages DROP COLUMN {}", column), ()) {
+            println!("Drop column failed with error {:?}", e);
+        }
+        conn.execute(&format!("ALTER TABLE pages ADD COLUMN {} INTEGER DEFAULT 0", column), ()).unwrap();
     }
-    println!("Adding column");
-    conn.execute("ALTER TABLE page_references ADD COLUMN reference_count INTEGER DEFAULT 0", ()).unwrap();
 
-    let mut count: u32 = 0;
     let start = Instant::now();
+    let pages: usize = conn.query_row("SELECT coalesce(max(id) + 1, 0) FROM pages", (), |row| row.get(0)).unwrap();
+    let total_links: u64 = conn.query_row("SELECT count(*) FROM links", (), |row| row.get(0)).unwrap();
+    println!("Counting {} links between {} pages", total_links.to_formatted_string(&Locale::en), pages.to_formatted_string(&Locale::en));
+
+    let mut is_redirect = vec![false; pages];
+    let mut resolved_targets: Vec<Option<u32>> = vec![None; pages];
+    {
+        let mut statement = conn.prepare("SELECT id, resolved_target FROM pages WHERE is_redirect").unwrap();
+        let mut rows = statement.query(()).unwrap();
+        while let Some(row) = rows.next().unwrap() {
+            let id: usize = row.get(0).unwrap();
+            is_redirect[id] = true;
+            resolved_targets[id] = row.get(1).unwrap();
+        }
+    }
 
-    let mut cached_update_statement = conn.prepare_cached("UPDATE page_references SET reference_count = reference_count + 1 WHERE title = ?").unwrap();
-
-    let mut statement = conn.prepare_cached("SELECT links FROM page_references").unwrap();
-    let mut rows = statement.query(()).unwrap();
-    let mut row = rows.next().unwrap();
-
-    while row.is_some() {
-        let links: String = row.unwrap().get(0).unwrap();
-
-        for link in links.split("<|>") {
-            if link.is_empty() {
-                continue;
-            }
-
-            let mut result =
-                cached_update_statement.execute(
-                (link,)
-            );
-
-            if let Err(_) = result {
-                let mut v: Vec<char> = link.chars().collect();
-                v[0] = v[0].to_uppercase().nth(0).unwrap();
-                let link: String = v.into_iter().collect();
-
-                result = cached_update_statement.execute(
-                    (link,)
-                );
+    let mut direct = vec![0u32; pages];
+    let mut with_redirects = vec![0u32; pages];
+    let mut count: u64 = 0;
+    {
+        let mut statement = conn.prepare("SELECT src_id, dst_id FROM links").unwrap();
+        let mut rows = statement.query(()).unwrap();
+        while let Some(row) = rows.next().unwrap() {
+            let src_id: usize = row.get(0).unwrap();
+            let dst_id: usize = row.get(1).unwrap();
+
+            count += 1;
+            if count.is_multiple_of(10_000_000) {
+                // Link counts pass u32::MAX, so these stay in floating point
+                let per_link = start.elapsed().div_f64(count as f64);
+                let eta = per_link.mul_f64(total_links.saturating_sub(count) as f64);
+                println!("{} links counted in {} [{:?}/link]. ETA: {}", count.to_formatted_string(&Locale::en), start.elapsed().hhmmss(), per_link, eta.hhmmss());
             }
 
-            if let Err(e) = result {
-                println!("Updating reference count failed: {:?}", e);
+            if is_redirect[src_id] {
+                continue;
             }
+            direct[dst_id] += 1;
+            let landing = resolved_targets[dst_id].map_or(dst_id, |target| target as usize);
+            with_redirects[landing] += 1;
         }
-
-        count += 1;
-        if count % 1000 == 0 {
-            if count < TOTAL_ARTICLES {
-                println!("{} pages completed in {} [{:?}/page]. ETA: {}", count, start.elapsed().hhmmss(), start.elapsed() / count, ((start.elapsed() / count) * (TOTAL_ARTICLES - count)).hhmmss())
-
-            }
-            else {
-                println!("{} pages completed in {} [{:?}/page]", count, start.elapsed().hhmmss(), start.elapsed() / count);
-            }
+    }
+    println!("Counted {} links in {}", count.to_formatted_string(&Locale::en), start.elapsed().hhmmss());
+
+    let mut update = conn.prepare("UPDATE pages SET reference_count = ?, reference_count_with_redirects = ? WHERE id = ?").unwrap();
+    let mut updated = 0;
+    conn.execute("BEGIN", ()).unwrap();
+    for id in 0..pages {
+        if direct[id] == 0 && with_redirects[id] == 0 {
+            continue;
         }
+        update.execute((direct[id], with_redirects[id], id)).unwrap();
 
-        row = rows.next().unwrap();
+        updated += 1;
+        if updated % UPDATE_BATCH == 0 {
+            conn.execute_batch("COMMIT; BEGIN").unwrap();
+            println!("{} pages updated in {}", updated.to_formatted_string(&Locale::en), start.elapsed().hhmmss());
+        }
     }
-
-
-}
\ No newline at end of file
+    conn.execute("COMMIT", ()).unwrap();
+    println!("Updated the counts of {} pages in {}", updated.to_formatted_string(&Locale::en), start.elapsed().hhmmss());
+}
@@ -0,0 +1,395 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process;
+use std::time::Instant;
+use hhmmss::Hhmmss;
+use num_format::{Locale, ToFormattedString};
+use rusqlite::Connection;
+use wiki_3::graph::{LinkGraph, PageId, SqliteGraph};
+use wiki_3::meta;
+use wiki_3::namespaces::Namespaces;
+use wiki_3::title::normalize;
+
+/// DOT is meant for drawing, so bigger exports need `--around` to pick a subgraph
+const DOT_MAX_PAGES: usize = 5_000;
+
+const USAGE: &str = "Usage: export <csv|graphml|gexf|neo4j|dot> <output> [--db <path>] [--redirects follow|keep|drop] \
+    [--namespaces <ids>] [--around <title>] [--depth <hops>]";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// `<output>-nodes.csv` and `<output>-edges.csv`
+    Csv,
+    GraphMl,
+    Gexf,
+    /// `<output>-nodes.csv` and `<output>-relationships.csv` with `neo4j-admin database import` headers
+    Neo4j,
+    Dot,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Redirects {
+    /// Leave redirects out and point links to them at their resolved target
+    Follow,
+    /// Export redirects as pages, each with a link to its target
+    Keep,
+    /// Leave out redirects and the links to them
+    Drop,
+}
+
+struct Settings {
+    format: Format,
+    output: String,
+    db_path: String,
+    redirects: Redirects,
+    /// Only pages in these namespaces are exported, all of them if `None`
+    namespaces: Option<HashSet<i32>>,
+    /// Only export the pages within `depth` links of this title
+    around: Option<String>,
+    depth: usize,
+}
+
+impl Settings {
+    fn from_args() -> Option<Settings> {
+        let mut format = None;
+        let mut output = None;
+        let mut db_path = "completed-table.db".to_string();
+        let mut redirects = Redirects::Follow;
+        let mut namespaces = None;
+        let mut around = None;
+        let mut depth = 1;
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--db" => db_path = args.next().unwrap(),
+                "--redirects" => {
+                    redirects = match args.next().unwrap().as_str() {
+                        "follow" => Redirects::Follow,
+                        "keep" => Redirects::Keep,
+                        "drop" => Redirects::Drop,
+                        other => panic!("Unknown redirect handling '{}'", other)
+                    }
+                }
+                "--namespaces" => {
+                    namespaces = Some(args.next().unwrap().split(',').map(|id| id.trim().parse().unwrap()).collect());
+                }
+                "--around" => around = Some(args.next().unwrap()),
+                "--depth" => depth = args.next().unwrap().parse().unwrap(),
+                _ if format.is_none() => {
+                    format = Some(match arg.as_str() {
+                        "csv" => Format::Csv,
+                        "graphml" => Format::GraphMl,
+                        "gexf" => Format::Gexf,
+                        "neo4j" => Format::Neo4j,
+                        "dot" => Format::Dot,
+                        other => panic!("Unknown format '{}'", other)
+                    });
+                }
+                _ if output.is_none() => output = Some(arg),
+                _ => panic!("Unexpected argument '{}'", arg)
+            }
+        }
+
+        Some(Settings { format: format?, output: output?, db_path, redirects, namespaces, around, depth })
+    }
+}
+
+struct Page {
+    namespace: i32,
+    is_redirect: bool,
+    resolved_target: Option<PageId>,
+}
+
+/// Which pages are exported, and where the links to each page lead
+struct Selection {
+    pages: Vec<Page>,
+    kept: Vec<bool>,
+    redirects: Redirects,
+}
+
+impl Selection {
+    fn new(conn: &Connection, settings: &Settings) -> Selection {
+        let mut pages = Vec::new();
+        let mut statement = conn.prepare("SELECT namespace, is_redirect, resolved_target FROM pages ORDER BY id").unwrap();
+        let mut rows = statement.query(()).unwrap();
+        while let Some(row) = rows.next().unwrap() {
+            pages.push(Page { namespace: row.get(0).unwrap(), is_redirect: row.get(1).unwrap(), resolved_target: row.get(2).unwrap() });
+        }
+
+        let kept = pages.iter()
+            .map(|page| {
+                settings.namespaces.as_ref().is_none_or(|namespaces| namespaces.contains(&page.namespace))
+                    && (settings.redirects == Redirects::Keep || !page.is_redirect)
+            })
+            .collect();
+        Selection { pages, kept, redirects: settings.redirects }
+    }
+
+    /// The exported page a link to `dst` points at, if any
+    fn target(&self, dst: PageId) -> Option<PageId> {
+        let page = &self.pages[dst as usize];
+        let target = match self.redirects {
+            Redirects::Follow if page.is_redirect => page.resolved_target?,
+            _ => dst
+        };
+        Some(target).filter(|target| self.kept[*target as usize])
+    }
+
+    /// Narrows the selection to the pages within `depth` links of `start`. `start` itself stays
+    /// out if the namespaces or redirect setting leave it out, but its links are still followed.
+    fn around(&mut self, graph: &impl LinkGraph, start: PageId, depth: usize) {
+        let mut reached = vec![false; self.pages.len()];
+        reached[start as usize] = true;
+        let mut layer = vec![start];
+        for _ in 0..depth {
+            let mut next = Vec::new();
+            for id in layer {
+                for link in graph.links(id).filter_map(|link| self.target(link)) {
+                    if !reached[link as usize] {
+                        reached[link as usize] = true;
+                        next.push(link);
+                    }
+                }
+            }
+            layer = next;
+        }
+        for (kept, reached) in self.kept.iter_mut().zip(reached) {
+            *kept &= reached;
+        }
+    }
+}
+
+/// Writes nodes and then edges in one of the [`Format`]s
+struct Exporter {
+    format: Format,
+    nodes: BufWriter<File>,
+    /// The separate edge file of the CSV formats
+    edges: Option<BufWriter<File>>,
+    edge_count: usize,
+}
+
+impl Exporter {
+    fn create(format: Format, output: &str) -> Exporter {
+        let create = |path: String| {
+            println!("Writing '{}'", path);
+            BufWriter::new(File::create(path).unwrap())
+        };
+        let (nodes, edges) = match format {
+            Format::Csv => (create(format!("{}-nodes.csv", output)), Some(create(format!("{}-edges.csv", output)))),
+            Format::Neo4j => (create(format!("{}-nodes.csv", output)), Some(create(format!("{}-relationships.csv", output)))),
+            _ => (create(output.to_string()), None)
+        };
+
+        let mut exporter = Exporter { format, nodes, edges, edge_count: 0 };
+        match format {
+            Format::Csv => {
+                writeln!(exporter.nodes, "id,title,namespace,is_redirect").unwrap();
+                writeln!(exporter.edges.as_mut().unwrap(), "source,target,ordinal").unwrap();
+            }
+            Format::Neo4j => {
+                writeln!(exporter.nodes, "id:ID,title,namespace:int,is_redirect:boolean,:LABEL").unwrap();
+                writeln!(exporter.edges.as_mut().unwrap(), ":START_ID,:END_ID,ordinal:int,:TYPE").unwrap();
+            }
+            Format::GraphMl => {
+                write!(
+                    exporter.nodes,
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                     <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n\
+                     <key id=\"title\" for=\"node\" attr.name=\"title\" attr.type=\"string\"/>\n\
+                     <key id=\"namespace\" for=\"node\" attr.name=\"namespace\" attr.type=\"int\"/>\n\
+                     <key id=\"is_redirect\" for=\"node\" attr.name=\"is_redirect\" attr.type=\"boolean\"/>\n\
+                     <key id=\"ordinal\" for=\"edge\" attr.name=\"ordinal\" attr.type=\"int\"/>\n\
+                     <graph id=\"links\" edgedefault=\"directed\">\n"
+                ).unwrap();
+            }
+            Format::Gexf => {
+                write!(
+                    exporter.nodes,
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                     <gexf xmlns=\"http://gexf.net/1.3\" version=\"1.3\">\n\
+                     <graph defaultedgetype=\"directed\">\n\
+                     <attributes class=\"node\">\n\
+                     <attribute id=\"namespace\" title=\"namespace\" type=\"integer\"/>\n\
+                     <attribute id=\"is_redirect\" title=\"is_redirect\" type=\"boolean\"/>\n\
+                     </attributes>\n\
+                     <attributes class=\"edge\">\n\
+                     <attribute id=\"ordinal\" title=\"ordinal\" type=\"integer\"/>\n\
+                     </attributes>\n\
+                     <nodes>\n"
+                ).unwrap();
+            }
+            Format::Dot => writeln!(exporter.nodes, "digraph links {{").unwrap(),
+        }
+        exporter
+    }
+
+    fn node(&mut self, id: PageId, title: &str, page: &Page) {
+        let out = &mut self.nodes;
+        match self.format {
+            Format::Csv => writeln!(out, "{},{},{},{}", id, csv(title), page.namespace, page.is_redirect).unwrap(),
+            Format::Neo4j => {
+                let label = if page.is_redirect { "Page;Redirect" } else { "Page" };
+                writeln!(out, "{},{},{},{},{}", id, csv(title), page.namespace, page.is_redirect, label).unwrap();
+            }
+            Format::GraphMl => writeln!(
+                out,
+                "<node id=\"n{}\"><data key=\"title\">{}</data><data key=\"namespace\">{}</data><data key=\"is_redirect\">{}</data></node>",
+                id, xml(title), page.namespace, page.is_redirect
+            ).unwrap(),
+            Format::Gexf => writeln!(
+                out,
+                "<node id=\"{}\" label=\"{}\"><attvalues><attvalue for=\"namespace\" value=\"{}\"/><attvalue for=\"is_redirect\" value=\"{}\"/></attvalues></node>",
+                id, xml(title), page.namespace, page.is_redirect
+            ).unwrap(),
+            Format::Dot => {
+                let style = if page.is_redirect { ", style=dashed" } else { "" };
+                writeln!(out, "  n{} [label=\"{}\"{}];", id, dot(title), style).unwrap();
+            }
+        }
+    }
+
+    /// Called once, between the last node and the first edge
+    fn start_edges(&mut self) {
+        if self.format == Format::Gexf {
+            write!(self.nodes, "</nodes>\n<edges>\n").unwrap();
+        }
+    }
+
+    fn edge(&mut self, src: PageId, dst: PageId, ordinal: u32, from_redirect: bool) {
+        self.edge_count += 1;
+        match self.format {
+            Format::Csv => writeln!(self.edges.as_mut().unwrap(), "{},{},{}", src, dst, ordinal).unwrap(),
+            Format::Neo4j => {
+                let kind = if from_redirect { "REDIRECTS_TO" } else { "LINKS_TO" };
+                writeln!(self.edges.as_mut().unwrap(), "{},{},{},{}", src, dst, ordinal, kind).unwrap();
+            }
+            Format::GraphMl => writeln!(
+                self.nodes,
+                "<edge source=\"n{}\" target=\"n{}\"><data key=\"ordinal\">{}</data></edge>",
+                src, dst, ordinal
+            ).unwrap(),
+            Format::Gexf => writeln!(
+                self.nodes,
+                "<edge id=\"{}\" source=\"{}\" target=\"{}\"><attvalues><attvalue for=\"ordinal\" value=\"{}\"/></attvalues></edge>",
+                self.edge_count, src, dst, ordinal
+            ).unwrap(),
+            Format::Dot => {
+                let style = if from_redirect { " [style=dashed]" } else { "" };
+                writeln!(self.nodes, "  n{} -> n{}{};", src, dst, style).unwrap();
+            }
+        }
+    }
+
+    fn finish(mut self) {
+        match self.format {
+            Format::GraphMl => write!(self.nodes, "</graph>\n</graphml>\n").unwrap(),
+            Format::Gexf => write!(self.nodes, "</edges>\n</graph>\n</gexf>\n").unwrap(),
+            Format::Dot => writeln!(self.nodes, "}}").unwrap(),
+            Format::Csv | Format::Neo4j => {}
+        }
+        self.nodes.flush().unwrap();
+        if let Some(edges) = &mut self.edges {
+            edges.flush().unwrap();
+        }
+    }
+}
+
+fn csv(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+fn xml(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn dot(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn main() {
+    let Some(settings) = Settings::from_args() else {
+        println!("{}", USAGE);
+        return;
+    };
+
+    let start = Instant::now();
+    let conn = meta::open_with_link_rows(&settings.db_path).unwrap();
+    let namespaces = Namespaces::load(&conn);
+    let graph = SqliteGraph::new(conn).unwrap();
+    let conn = graph.conn();
+
+    let mut selection = Selection::new(conn, &settings);
+    if let Some(title) = &settings.around {
+        let title = normalize(title, &namespaces);
+        let Some(mut id) = graph.id(&title) else {
+            println!("There is no page called '{}'", title);
+            return;
+        };
+        if settings.redirects == Redirects::Follow {
+            id = selection.target(id).unwrap_or(id);
+        }
+        selection.around(&graph, id, settings.depth);
+    }
+
+    let page_count = selection.kept.iter().filter(|kept| **kept).count();
+    if settings.format == Format::Dot && page_count > DOT_MAX_PAGES {
+        println!(
+            "DOT is for small subgraphs, but {} pages were selected. Pass --around <title> to pick fewer than {}.",
+            page_count.to_formatted_string(&Locale::en), DOT_MAX_PAGES.to_formatted_string(&Locale::en)
+        );
+        process::exit(1);
+    }
+
+    let mut exporter = Exporter::create(settings.format, &settings.output);
+    {
+        let mut statement = conn.prepare("SELECT id, title FROM pages ORDER BY id").unwrap();
+        let mut rows = statement.query(()).unwrap();
+        while let Some(row) = rows.next().unwrap() {
+            let id: PageId = row.get(0).unwrap();
+            if selection.kept[id as usize] {
+                exporter.node(id, row.get_ref(1).unwrap().as_str().unwrap(), &selection.pages[id as usize]);
+            }
+        }
+    }
+    println!("Exported {} pages in {}", page_count.to_formatted_string(&Locale::en), start.elapsed().hhmmss());
+
+    exporter.start_edges();
+    {
+        // A page can link to a redirect and to its target, which is one edge once redirects are followed
+        let mut targets = HashSet::new();
+        let mut last_src = None;
+
+        let mut statement = conn.prepare("SELECT src_id, dst_id, ordinal FROM links ORDER BY src_id, ordinal").unwrap();
+        let mut rows = statement.query(()).unwrap();
+        while let Some(row) = rows.next().unwrap() {
+            let src: PageId = row.get(0).unwrap();
+            if !selection.kept[src as usize] {
+                continue;
+            }
+            if last_src != Some(src) {
+                last_src = Some(src);
+                targets.clear();
+            }
+
+            let Some(dst) = selection.target(row.get(1).unwrap()) else { continue; };
+            if dst == src || !targets.insert(dst) {
+                continue;
+            }
+            exporter.edge(src, dst, row.get(2).unwrap(), selection.pages[src as usize].is_redirect);
+
+            if exporter.edge_count.is_multiple_of(10_000_000) {
+                println!("Exported {} links in {}", exporter.edge_count.to_formatted_string(&Locale::en), start.elapsed().hhmmss());
+            }
+        }
+    }
+
+    let edge_count = exporter.edge_count;
+    exporter.finish();
+    println!(
+        "Exported {} pages and {} links in {}",
+        page_count.to_formatted_string(&Locale::en), edge_count.to_formatted_string(&Locale::en), start.elapsed().hhmmss()
+    );
+}
@@ -0,0 +1,20 @@
+use std::env;
+use std::time::Instant;
+use hhmmss::Hhmmss;
+use wiki_3::csr::{CsrGraph, write_csr};
+use wiki_3::graph::LinkGraph;
+use wiki_3::meta;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let db_path = args.next().unwrap_or("completed-table.db".to_string());
+    let csr_path = args.next().unwrap_or("graph.csr".to_string());
+
+    let start = Instant::now();
+    let conn = meta::open_with_link_rows(&db_path).unwrap();
+    println!("Writing the link graph in '{}' to '{}'", db_path, csr_path);
+    write_csr(&conn, &csr_path).unwrap();
+
+    let graph = CsrGraph::open(&csr_path).unwrap();
+    println!("Wrote {} pages in {}", graph.len(), start.elapsed().hhmmss());
+}
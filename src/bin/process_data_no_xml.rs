@@ -1,28 +1,421 @@
+use std::borrow::Cow;
 use std::cmp::min;
-use std::fs;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::{env, fs, thread};
+use std::io::BufRead;
+use std::sync::{Arc, mpsc, Mutex};
+use std::sync::mpsc::Receiver;
 use std::time::Instant;
 use hhmmss::Hhmmss;
 use rusqlite::{Connection, ToSql};
 use rusqlite::types::{ToSqlOutput, ValueRef};
+use wiki_3::entities::decode_entities;
+use wiki_3::meta::{self, has_table, SCHEMA_VERSION};
+use wiki_3::multistream::{Block, decompress_block, index_path_for, MultistreamDump};
+use wiki_3::namespaces::Namespaces;
+use wiki_3::schema::build_link_tables;
+use wiki_3::title::normalize;
+use wiki_3::wikitext::{mask_hidden, parse_redirect, Redirect, SectionPolicy, transcluded_content, transclusions};
+
+const DEFAULT_DUMP: &str = "enwiki-20231101-pages-articles-multistream.xml.bz2";
+const TOTAL_ARTICLES: u32 = 23_100_000;
+
+struct ParsedPage {
+    title: String,
+    namespace: i32,
+    links: String,
+    is_redirect: bool,
+    decoded_title: bool,
+    decoded_links: bool,
+}
+
+#[derive(Default, Clone)]
+struct Stats {
+    pages: u32,
+    /// Rows whose title contained XML entities
+    decoded_titles: u32,
+    /// Rows with at least one link or redirect target that contained entities
+    decoded_links: u32,
+}
+
+/// How far ingestion got, committed together with the pages it covers
+struct Checkpoint {
+    /// Index of the first stream not yet written
+    next_block: usize,
+    /// Byte offset of that stream in the dump
+    offset: u64,
+    stats: Stats,
+    last_title: Option<String>,
+}
+
+struct Settings {
+    dump_path: String,
+    index_path: String,
+    threads: usize,
+    /// Ids of the namespaces whose pages and link targets are kept
+    namespaces: HashSet<i32>,
+    /// Magic words that start a redirect when a page has no `<redirect>` element
+    redirect_words: Vec<String>,
+    /// Whether links inside `<ref>` footnotes are kept
+    include_ref_links: bool,
+    /// Whether links from transcluded templates are added to pages
+    templates: bool,
+    sections: SectionPolicy,
+    /// Continue from the checkpoint in an unfinished `table.db` instead of starting over
+    resume: bool,
+}
+
+impl Settings {
+    fn from_args() -> Settings {
+        let mut dump_path = DEFAULT_DUMP.to_string();
+        let mut index_path = None;
+        let mut threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let mut namespaces = HashSet::from([0]);
+        // MediaWiki accepts the English magic word on every wiki
+        let mut redirect_words = vec![DEFAULT_REDIRECT_WORD.to_string()];
+        let mut include_ref_links = false;
+        let mut templates = true;
+        let mut sections = SectionPolicy::exclude(&DEFAULT_EXCLUDED_SECTIONS);
+        let mut resume = false;
+
+        let mut args = env::args().skip(1);
+        let mut positional = 0;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--threads" => threads = args.next().unwrap().parse().unwrap(),
+                "--namespaces" => {
+                    namespaces = args.next().unwrap().split(',').map(|id| id.trim().parse().unwrap()).collect();
+                }
+                "--redirect-words" => {
+                    redirect_words.extend(args.next().unwrap().split(',').map(|w| w.trim().to_string()));
+                }
+                "--include-ref-links" => include_ref_links = true,
+                "--no-templates" => templates = false,
+                "--resume" => resume = true,
+                "--include-sections" => {
+                    sections = SectionPolicy::include(&args.next().unwrap().split(',').collect::<Vec<_>>());
+                }
+                "--exclude-sections" => {
+                    sections = SectionPolicy::exclude(&args.next().unwrap().split(',').collect::<Vec<_>>());
+                }
+                _ => {
+                    match positional {
+                        0 => dump_path = arg,
+                        1 => index_path = Some(arg),
+                        _ => panic!("Unexpected argument '{}'", arg)
+                    }
+                    positional += 1;
+                }
+            }
+        }
+        let index_path = index_path.unwrap_or_else(|| index_path_for(&dump_path));
+
+        Settings { dump_path, index_path, threads, namespaces, redirect_words, include_ref_links, templates, sections, resume }
+    }
+}
+
+/// Everything the workers need to parse a block
+struct Context {
+    settings: Settings,
+    namespaces: Namespaces,
+    /// Links added by each template, by title
+    templates: HashMap<String, String>,
+}
+
+impl Context {
+    fn keeps(&self, namespace: i32) -> bool {
+        self.settings.namespaces.contains(&namespace)
+    }
+
+    /// Whether a link or redirect target points into a kept namespace
+    fn keeps_target(&self, target: &str) -> bool {
+        self.keeps(self.namespaces.namespace_of(target))
+    }
+}
 
 fn main() {
-    let file = File::open("enwiki-20231101-pages-articles-multistream.xml").unwrap();
-    let reader = BufReader::new(file);
+    let settings = Settings::from_args();
+    let threads = settings.threads;
 
-    let mut db = DB::new(1000, 100_000);
+    println!("Reading '{}' using index '{}'", settings.dump_path, settings.index_path);
+    let mut dump = MultistreamDump::open(&settings.dump_path, &settings.index_path).unwrap();
+    println!("Found {} streams - parsing with {} worker threads", dump.blocks().len(), threads);
+
+    let header = decompress_block(&dump.read_block(&dump.header()).unwrap()).unwrap();
+    let namespaces = Namespaces::from_siteinfo(&String::from_utf8_lossy(&header));
+    let mut kept: Vec<i32> = settings.namespaces.iter().copied().collect();
+    kept.sort();
+    let kept: Vec<String> = kept.into_iter().map(|id| match namespaces.name(id) {
+        Some("") => format!("{id} (main)"),
+        Some(name) => format!("{id} ({name})"),
+        None => format!("{id} (unknown)")
+    }).collect();
+    println!("Keeping namespaces: {}", kept.join(", "));
+    println!("Taking links from sections - {}", settings.sections.describe());
+
+    let mut context = Context { settings, namespaces, templates: HashMap::new() };
 
-    let mut count: u32 = 0;
     let start = Instant::now();
-    const TOTAL_ARTICLES: u32 = 23_100_000;
 
-    let mut lines = reader.lines();
+    let db = DB::new(1000, 100_000, context.settings.resume);
+    let checkpoint = if context.settings.resume {
+        let checkpoint = db.load_checkpoint()
+            .expect("No checkpoint in table.db - start again without --resume");
+        let mismatches: Vec<String> = build_settings(&context.settings).into_iter()
+            .filter_map(|(key, value)| match meta::get(&db.conn, key) {
+                Some(recorded) if recorded == value => None,
+                Some(recorded) => Some(format!("{} was '{}', now '{}'", key, recorded, value)),
+                None => Some(format!("{} wasn't recorded, now '{}'", key, value))
+            })
+            .collect();
+        if !mismatches.is_empty() {
+            panic!("table.db was started with different settings - start again without --resume or use the same ones:\n  {}", mismatches.join("\n  "));
+        }
+
+        println!(
+            "Resuming from stream {} at byte {} after {} articles (last written: {})",
+            checkpoint.next_block, checkpoint.offset, checkpoint.stats.pages, checkpoint.last_title.as_deref().unwrap_or("none")
+        );
+        checkpoint
+    } else {
+        context.namespaces.save(&db.conn).unwrap();
+        record_build_settings(&db.conn, &context.settings);
+        let checkpoint = Checkpoint { next_block: 0, offset: dump.header().len, stats: Stats::default(), last_title: None };
+        db.save_checkpoint(&checkpoint);
+        checkpoint
+    };
+
+    if context.settings.templates && context.settings.resume && has_table(&db.conn, "template_links") {
+        context.templates = resolve_template_redirects(load_templates(&db.conn));
+        println!("Loaded {} templates from table.db", context.templates.len());
+    }
+    else if context.settings.templates {
+        let prefix = format!("{}:", context.namespaces.name(TEMPLATE_NAMESPACE).unwrap());
+        let template_blocks = dump.blocks_containing(&prefix).unwrap();
+        println!("Reading templates from {} streams", template_blocks.len());
+
+        let shared = Arc::new(context);
+        let templates = parse_in_parallel(&mut dump, &template_blocks, &shared, parse_template_block,
+            |receiver| in_block_order(receiver).flatten().collect::<Vec<_>>()
+        );
+        context = Arc::try_unwrap(shared).ok().unwrap();
+
+        save_templates(&db.conn, &templates);
+        context.templates = resolve_template_redirects(templates);
+        println!("Read {} templates in {}", context.templates.len(), start.elapsed().hhmmss());
+    }
+
+    let context = Arc::new(context);
+    let blocks = dump.blocks().to_vec();
+    let remaining = blocks[checkpoint.next_block..].to_vec();
+    let (db, stats) = parse_in_parallel(&mut dump, &remaining, &context, parse_block,
+        move |receiver| write_pages(db, receiver, blocks, checkpoint, start)
+    );
+
+    // A resumed run may have been interrupted after the links were built
+    if has_table(&db.conn, "pages") {
+        db.conn.execute("DROP TABLE IF EXISTS page_references", ()).unwrap();
+    }
+    else {
+        println!("Resolving link targets to page ids");
+        let built = build_link_tables(&db.conn).unwrap();
+        println!("Built {} pages with {} links and {} unresolved links in {}", built.pages, built.links, built.unresolved, start.elapsed().hhmmss());
+        println!(
+            "Resolved {} redirects: {} broken, {} loops and {} double redirects",
+            built.redirects.redirects, built.redirects.broken, built.redirects.loops, built.redirects.double
+        );
+    }
+    record_build_counts(&db.conn, &start, context.settings.resume);
+    drop(db);
+
+    // Only a finished database is renamed, so an interrupted run can still be resumed
+    fs::rename("table.db", "completed-table.db").unwrap();
+
+    println!("Completed {} articles in {} [{:?}/article]", stats.pages, start.elapsed().hhmmss(), start.elapsed() / stats.pages.max(1));
+    println!("Decoded entities in {} titles and in the link targets of {} pages", stats.decoded_titles, stats.decoded_links);
+}
+
+/// The settings that decide what ends up in the table, as recorded in the `meta` table
+fn build_settings(settings: &Settings) -> Vec<(&'static str, String)> {
+    let mut namespaces: Vec<i32> = settings.namespaces.iter().copied().collect();
+    namespaces.sort();
+
+    let mut recorded = vec![
+        ("schema_version", SCHEMA_VERSION.to_string()),
+        ("parser", "process_data_no_xml".to_string()),
+        ("parser_version", env!("CARGO_PKG_VERSION").to_string()),
+        ("dump_file", settings.dump_path.rsplit(['/', '\\']).next().unwrap().to_string()),
+    ];
+    if let Some(date) = meta::dump_date(&settings.dump_path) {
+        recorded.push(("dump_date", date));
+    }
+    recorded.extend([
+        ("namespaces", namespaces.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(",")),
+        ("redirect_words", settings.redirect_words.join(",")),
+        ("include_ref_links", settings.include_ref_links.to_string()),
+        ("templates", settings.templates.to_string()),
+        ("section_policy", settings.sections.describe()),
+    ]);
+    recorded
+}
+
+/// Records where the database comes from and what was filtered out in the `meta` table
+fn record_build_settings(conn: &Connection, settings: &Settings) {
+    for (key, value) in build_settings(settings) {
+        meta::set(conn, key, value);
+    }
+}
+
+/// Records the size of the finished database and how long this run took in the `meta` table
+fn record_build_counts(conn: &Connection, start: &Instant, resumed: bool) {
+    for (key, query) in [
+        ("pages", "SELECT count(*) FROM pages"),
+        ("links", "SELECT count(*) FROM links"),
+        ("unresolved_links", "SELECT count(*) FROM unresolved_links"),
+        ("redirects", "SELECT count(*) FROM pages WHERE is_redirect"),
+    ] {
+        meta::set(conn, key, conn.query_row(query, (), |row| row.get::<_, u64>(0)).unwrap());
+    }
+    meta::set(conn, "build_duration", start.elapsed().hhmmss());
+    meta::set(conn, "resumed", resumed);
+}
+
+/// Decompresses and parses `blocks` on a pool of worker threads. `consume` runs on its own thread
+/// and receives each block's output tagged with the block's index in `blocks`.
+fn parse_in_parallel<T, R>(
+    dump: &mut MultistreamDump,
+    blocks: &[Block],
+    context: &Arc<Context>,
+    parse: fn(&[u8], &Context) -> T,
+    consume: impl FnOnce(Receiver<(usize, T)>) -> R + Send + 'static
+) -> R
+    where T: Send + 'static, R: Send + 'static
+{
+    let threads = context.settings.threads;
+    let (block_sender, block_receiver) = mpsc::sync_channel::<(usize, Vec<u8>)>(threads * 4);
+    let (output_sender, output_receiver) = mpsc::sync_channel::<(usize, T)>(threads * 4);
+    let block_receiver = Arc::new(Mutex::new(block_receiver));
+
+    let workers: Vec<_> = (0..threads).map(|_| {
+        let block_receiver = block_receiver.clone();
+        let output_sender = output_sender.clone();
+        let context = context.clone();
+        thread::spawn(move || {
+            loop {
+                // Release the lock before decompressing so other workers can take the next block
+                let next = block_receiver.lock().unwrap().recv();
+                let Ok((i, compressed)) = next else { break; };
+
+                let data = decompress_block(&compressed).unwrap();
+                output_sender.send((i, parse(&data, &context))).unwrap();
+            }
+        })
+    }).collect();
+    drop(output_sender);
+
+    let consumer = thread::spawn(move || consume(output_receiver));
+
+    for (i, block) in blocks.iter().enumerate() {
+        block_sender.send((i, dump.read_block(block).unwrap())).unwrap();
+    }
+    drop(block_sender);
+
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    consumer.join().unwrap()
+}
+
+/// Reorders worker output back into block order
+fn in_block_order<T>(receiver: Receiver<(usize, T)>) -> impl Iterator<Item = T> {
+    let mut next_block = 0;
+    let mut pending = BTreeMap::new();
+    let mut received = receiver.into_iter();
+
+    std::iter::from_fn(move || {
+        loop {
+            if let Some(output) = pending.remove(&next_block) {
+                next_block += 1;
+                return Some(output);
+            }
+
+            match received.next() {
+                Some((i, output)) => { pending.insert(i, output); }
+                None => {
+                    assert!(pending.is_empty(), "Blocks missing from worker output");
+                    return None;
+                }
+            }
+        }
+    })
+}
+
+/// Owns the database connection and inserts parsed blocks in dump order, so duplicate titles
+/// resolve the same way regardless of which worker finishes first. Writes only happen between
+/// streams, so every checkpoint covers whole streams.
+fn write_pages(mut db: DB, page_receiver: Receiver<(usize, Vec<ParsedPage>)>, blocks: Vec<Block>, mut checkpoint: Checkpoint, start: Instant) -> (DB, Stats) {
+    let resumed_pages = checkpoint.stats.pages;
+    let first_block = checkpoint.next_block;
+    let mut stats = checkpoint.stats.clone();
+
+    for (i, pages) in in_block_order(page_receiver).enumerate() {
+        let next_block = first_block + i + 1;
+        let last_title = pages.last().map(|page| page.title.clone());
+
+        for page in pages {
+            stats.decoded_titles += page.decoded_title as u32;
+            stats.decoded_links += page.decoded_links as u32;
+            db.cache(page);
+
+            stats.pages += 1;
+            let count = stats.pages;
+            let done = count - resumed_pages;
+            if count.is_multiple_of(50_000) {
+                if count < TOTAL_ARTICLES {
+                    println!("Completed {} articles in {} [{:?}/article]. ETA: {}", count, start.elapsed().hhmmss(), start.elapsed() / done, ((start.elapsed() / done) * (TOTAL_ARTICLES - count)).hhmmss())
+                }
+                else {
+                    println!("Completed {} articles in {} [{:?}/article]", count, start.elapsed().hhmmss(), start.elapsed() / done);
+                }
+            }
+        }
+
+        checkpoint = Checkpoint {
+            next_block,
+            offset: blocks.get(next_block).map(|block| block.offset).unwrap_or_else(|| blocks.last().map(|b| b.offset + b.len).unwrap_or(0)),
+            stats: stats.clone(),
+            last_title: last_title.or(checkpoint.last_title),
+        };
+        if db.to_insert.len() >= db.insert_threshold {
+            db.write_to_db(&checkpoint);
+        }
+    }
+
+    db.write_to_db(&checkpoint);
+    (db, stats)
+}
+
+const TITLE_TAG: &str = "    <title>";
+const END_TITLE_TAG: &str = "</title>";
+const NS_TAG: &str = "    <ns>";
+const END_NS_TAG: &str = "</ns>";
+const REDIRECT_TAG: &str = "    <redirect title=\"";
+const END_REDIRECT_TAG: &str = "\" />";
+const TEXT_TAG: &str = "      <text";
+const END_TEXT_TAG: &str = "</text>";
 
-    const TITLE_TAG: &str = "    <title>";
-    const END_TITLE_TAG: &str = "</title>";
-    const TEXT_TAG: &str = "      <text";
-    const END_TEXT_TAG: &str = "</text>";
+/// A page as it appears in the dump, with its title and body still XML-escaped
+struct ScannedPage {
+    title: String,
+    namespace: i32,
+    redirect: Option<String>,
+    body: String,
+}
+
+/// Scans the decompressed lines of one stream for pages in the namespaces `wanted` accepts.
+/// Pages never span streams.
+fn scan_block(data: &[u8], wanted: impl Fn(i32) -> bool, mut on_page: impl FnMut(ScannedPage)) {
+    let mut lines = data.lines();
 
     'main_loop: loop {
         let title;
@@ -33,10 +426,7 @@ fn main() {
                     println!("Breaking main loop due to error reading line: {:?}", e);
                     break 'main_loop;
                 }
-                None => {{
-                    println!("No more lines");
-                    break 'main_loop;
-                }}
+                None => break 'main_loop
             };
             if line.len() < TITLE_TAG.len() || !line.is_char_boundary(TITLE_TAG.len()) || &line[..TITLE_TAG.len()] != TITLE_TAG {
                 continue;
@@ -46,13 +436,8 @@ fn main() {
             break;
         }
 
-        for pattern in FORBIDDEN_PATTERNS {
-            if title.find(pattern).is_some() {
-                continue 'main_loop;
-            }
-        }
-
-
+        let mut namespace = None;
+        let mut redirect = None;
         let mut body = String::with_capacity(30);
         loop {
             let line = match lines.next() {
@@ -61,15 +446,42 @@ fn main() {
                     println!("Breaking main loop due to error reading line: {:?}", e);
                     break 'main_loop;
                 }
-                None => {{
-                    println!("No more lines");
+                None => {
+                    println!("Stream ended before text of '{}'", title);
                     break 'main_loop;
-                }}
+                }
             };
+
+            if namespace.is_none() {
+                if let Some(ns) = line.strip_prefix(NS_TAG).and_then(|l| l.strip_suffix(END_NS_TAG)) {
+                    let Ok(ns) = ns.parse::<i32>() else {
+                        println!("Skipping '{}', its namespace '{}' isn't a number", title, ns);
+                        continue 'main_loop;
+                    };
+                    if !wanted(ns) {
+                        continue 'main_loop;
+                    }
+                    namespace = Some(ns);
+                    continue;
+                }
+            }
+
+            if let Some(target) = line.strip_prefix(REDIRECT_TAG).and_then(|l| l.strip_suffix(END_REDIRECT_TAG)) {
+                redirect = Some(target.to_string());
+                continue;
+            }
+
             if line.len() < TEXT_TAG.len() || !line.is_char_boundary(TEXT_TAG.len()) || &line[..TEXT_TAG.len()] != TEXT_TAG {
                 continue;
             }
 
+            // Empty pages have a self-closing <text ... />. This deliberately differs from the
+            // single-threaded parser this replaced, which read on into the next page's text
+            // looking for </text> and so dropped the page after every empty one.
+            if line.ends_with("/>") {
+                break;
+            }
+
             let start = line.find('>');
             let mut line_owned = line[(start.unwrap() + '>'.len_utf8())..].to_string();
             let mut line = line_owned.as_str();
@@ -85,76 +497,210 @@ fn main() {
                 body += line;
                 if end { break; }
                 else { body.push('\n'); }
-                line_owned = lines.next().unwrap().unwrap();
+                line_owned = match lines.next() {
+                    Some(Ok(line)) => line,
+                    _ => {
+                        println!("Stream ended inside text of '{}'", title);
+                        break 'main_loop;
+                    }
+                };
                 line = line_owned.as_str();
             }
             break;
         }
 
-        let (links, is_redirect) = match get_links_from_body(body, &title) {
+        let Some(namespace) = namespace else {
+            println!("Page '{}' has no namespace", title);
+            continue;
+        };
+
+        on_page(ScannedPage { title, namespace, redirect, body });
+    }
+}
+
+/// Decodes the title and body of a scanned page, returning whether the title contained entities
+fn decode_page(page: &mut ScannedPage, context: &Context) -> bool {
+    let decoded_title = match decode_entities(&page.title) {
+        Cow::Owned(decoded) => Some(decoded),
+        Cow::Borrowed(_) => None
+    };
+    let had_entities = decoded_title.is_some();
+    page.title = normalize(decoded_title.as_ref().unwrap_or(&page.title), &context.namespaces);
+
+    if page.body.contains('&') {
+        page.body = decode_entities(&page.body).into_owned();
+    }
+
+    had_entities
+}
+
+fn parse_block(data: &[u8], context: &Context) -> Vec<ParsedPage> {
+    let mut pages = Vec::with_capacity(100);
+
+    scan_block(data, |ns| context.keeps(ns), |mut page| {
+        let decoded_title = decode_page(&mut page, context);
+
+        let links = match get_links_from_body(page.body, page.redirect, &page.title, context) {
             Ok(links) => links,
             Err(e) => {
                 println!("{}", e);
-                continue;
+                return;
             }
         };
 
-        // db.cache(title.to_lowercase(), links, is_redirect);
-        db.cache(title, links, is_redirect);
+        pages.push(ParsedPage {
+            title: page.title,
+            namespace: page.namespace,
+            links: links.links,
+            is_redirect: links.is_redirect,
+            decoded_title,
+            decoded_links: links.decoded_entities,
+        });
+    });
 
-        count += 1;
-        if count % 50_000 == 0 {
-            if count < TOTAL_ARTICLES {
-                println!("Completed {} articles in {} [{:?}/article]. ETA: {}", count, start.elapsed().hhmmss(), start.elapsed() / count, ((start.elapsed() / count) * (TOTAL_ARTICLES - count)).hhmmss())
-            }
-            else {
-                println!("Completed {} articles in {} [{:?}/article]", count, start.elapsed().hhmmss(), start.elapsed() / count);
-            }
+    pages
+}
+
+const TEMPLATE_NAMESPACE: i32 = 10;
+
+/// Templates whose positional arguments are page titles, e.g. `{{Main|History of Bedford}}`
+const HATNOTE_TEMPLATES: [&str; 6] = ["Main", "Main article", "See also", "Further", "Further information", "Details"];
+
+struct TemplatePage {
+    title: String,
+    links: String,
+    is_redirect: bool,
+}
+
+/// Extracts the links every template in a block adds to the pages that transclude it
+fn parse_template_block(data: &[u8], context: &Context) -> Vec<TemplatePage> {
+    let mut templates = Vec::new();
+
+    scan_block(data, |ns| ns == TEMPLATE_NAMESPACE, |mut page| {
+        decode_page(&mut page, context);
+
+        let body = transcluded_content(&page.body);
+        match get_links_from_body(body, page.redirect, &page.title, context) {
+            Ok(links) => templates.push(TemplatePage { title: page.title, links: links.links, is_redirect: links.is_redirect }),
+            Err(e) => println!("{}", e)
         }
+    });
+
+    templates
+}
+
+/// Saves the templates in one transaction, so `template_links` only exists once it is complete
+fn save_templates(conn: &Connection, templates: &[TemplatePage]) {
+    conn.execute("BEGIN", ()).unwrap();
+    conn.execute("DROP TABLE IF EXISTS template_links", ()).unwrap();
+    conn.execute(
+        "CREATE TABLE template_links (
+            title TEXT PRIMARY KEY,
+            links TEXT,
+            is_redirect INTEGER
+         )",
+        ()
+    ).unwrap();
+
+    let mut statement = conn.prepare("INSERT OR IGNORE INTO template_links VALUES (?, ?, ?)").unwrap();
+    for template in templates {
+        statement.execute((&template.title, &template.links, template.is_redirect)).unwrap();
     }
+    conn.execute("COMMIT", ()).unwrap();
+}
+
+fn load_templates(conn: &Connection) -> Vec<TemplatePage> {
+    let mut statement = conn.prepare("SELECT title, links, is_redirect FROM template_links").unwrap();
+    statement.query_map((), |row| Ok(TemplatePage { title: row.get(0)?, links: row.get(1)?, is_redirect: row.get(2)? }))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
 
-    db.write_to_db();
+/// Maps each template title to the links it adds, following redirects between templates
+fn resolve_template_redirects(templates: Vec<TemplatePage>) -> HashMap<String, String> {
+    const MAX_REDIRECT_DEPTH: usize = 5;
 
-    drop(db);
-    fs::rename("table.db", "completed-table.db").unwrap();
+    let mut by_title: HashMap<String, TemplatePage> = HashMap::with_capacity(templates.len());
+    for template in templates {
+        by_title.entry(template.title.clone()).or_insert(template);
+    }
 
-    println!("Completed {} articles in {} [{:?}/article]", count, start.elapsed().hhmmss(), start.elapsed() / count);
+    by_title.iter().filter_map(|(title, template)| {
+        let mut template = template;
+        for _ in 0..MAX_REDIRECT_DEPTH {
+            if !template.is_redirect {
+                return Some((title.clone(), template.links.clone()));
+            }
+            template = by_title.get(&template.links)?;
+        }
+        None
+    }).collect()
 }
 
 struct DB {
     conn: Connection,
     batch_size: usize,
     insert_threshold: usize,
-    to_insert: Vec<(String, String, bool)>
+    to_insert: Vec<ParsedPage>
 }
 
 impl DB {
-    pub fn new(batch_size: usize, insert_threshold: usize) -> Self {
-        let conn = Connection::open("table.db").unwrap();
+    /// Opens `table.db`, clearing it unless `resume` is set
+    pub fn new(batch_size: usize, insert_threshold: usize, resume: bool) -> Self {
+        if resume && !fs::exists("table.db").unwrap() {
+            panic!("Nothing to resume - table.db does not exist");
+        }
+        DB::with_connection(Connection::open("table.db").unwrap(), batch_size, insert_threshold, resume)
+    }
 
+    /// Sets up the tables of `table.db` on an open connection, clearing them unless `resume` is set
+    fn with_connection(conn: Connection, batch_size: usize, insert_threshold: usize, resume: bool) -> Self {
+        // A journal is needed so a crash rolls back to the last checkpoint instead of corrupting the file
         conn.execute_batch(
-            "PRAGMA journal_mode = OFF;
-              PRAGMA synchronous = 0;
+            "PRAGMA journal_mode = WAL;
+              PRAGMA synchronous = NORMAL;
               PRAGMA cache_size = 1000000;
               PRAGMA locking_mode = EXCLUSIVE;
               PRAGMA temp_store = MEMORY;",
         ).unwrap();
 
-        conn.execute(
-            "DROP TABLE IF EXISTS page_references",
-            ()
-        ).unwrap();
+        if !resume {
+            conn.execute(
+                "DROP TABLE IF EXISTS page_references",
+                ()
+            ).unwrap();
 
-        conn.execute(
-            "DROP TABLE IF EXISTS page_reference_errors",
-            ()
-        ).unwrap();
+            conn.execute(
+                "DROP TABLE IF EXISTS page_reference_errors",
+                ()
+            ).unwrap();
+
+            conn.execute(
+                "DROP TABLE IF EXISTS checkpoint",
+                ()
+            ).unwrap();
+
+            conn.execute(
+                "DROP TABLE IF EXISTS template_links",
+                ()
+            ).unwrap();
+
+            conn.execute_batch(
+                "DROP TABLE IF EXISTS pages;
+                 DROP TABLE IF EXISTS links;
+                 DROP TABLE IF EXISTS unresolved_links;
+                 DROP TABLE IF EXISTS backlinks;
+                 DROP TABLE IF EXISTS meta;"
+            ).unwrap();
+        }
 
         conn.execute(
             "CREATE TABLE IF NOT EXISTS page_references (
             title TEXT PRIMARY KEY,
             links TEXT,
-            is_redirect INTEGER
+            is_redirect INTEGER,
+            namespace INTEGER
          )",
             ()
         ).unwrap();
@@ -163,11 +709,25 @@ impl DB {
             "CREATE TABLE IF NOT EXISTS page_reference_errors (
             title TEXT,
             links TEXT,
-            is_redirect INTEGER
+            is_redirect INTEGER,
+            namespace INTEGER,
+            cause TEXT
          )",
             ()
         ).unwrap();
 
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS checkpoint (
+            id INTEGER PRIMARY KEY CHECK (id = 0),
+            next_block INTEGER,
+            byte_offset INTEGER,
+            pages INTEGER,
+            decoded_titles INTEGER,
+            decoded_links INTEGER,
+            last_title TEXT
+         )",
+            ()
+        ).unwrap();
 
         Self {
             conn,
@@ -177,39 +737,60 @@ impl DB {
         }
     }
 
-    pub fn write_to_db(&mut self) {
-        if self.to_insert.len() == 0 {
-            println!("Cancelling db write as cache is empty");
-            return;
-        }
+    pub fn load_checkpoint(&self) -> Option<Checkpoint> {
+        self.conn.query_row(
+            "SELECT next_block, byte_offset, pages, decoded_titles, decoded_links, last_title FROM checkpoint",
+            (),
+            |row| Ok(Checkpoint {
+                next_block: row.get(0)?,
+                offset: row.get(1)?,
+                stats: Stats { pages: row.get(2)?, decoded_titles: row.get(3)?, decoded_links: row.get(4)? },
+                last_title: row.get(5)?,
+            })
+        ).ok()
+    }
+
+    pub fn save_checkpoint(&self, checkpoint: &Checkpoint) {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO checkpoint VALUES (0, ?, ?, ?, ?, ?, ?)",
+            (
+                checkpoint.next_block, checkpoint.offset, checkpoint.stats.pages, checkpoint.stats.decoded_titles,
+                checkpoint.stats.decoded_links, &checkpoint.last_title
+            )
+        ).unwrap();
+    }
 
+    /// Inserts the cached pages and saves `checkpoint` in one transaction
+    pub fn write_to_db(&mut self, checkpoint: &Checkpoint) {
         let start = Instant::now();
         println!("Writing {} entries to database", self.to_insert.len());
+        self.conn.execute("BEGIN", ()).unwrap();
 
         let mut cached_statement =
             self.conn.prepare_cached(
-                format!("INSERT INTO page_references VALUES {}", " (?, ?, ?),".repeat(self.batch_size - 1) + " (?, ?, ?)")
+                format!("INSERT INTO page_references VALUES {}", " (?, ?, ?, ?),".repeat(self.batch_size - 1) + " (?, ?, ?, ?)")
                     .as_str()).unwrap();
 
         let mut individual_cached_statement =
-            self.conn.prepare_cached("INSERT INTO page_references VALUES (?, ?, ?)").unwrap();
+            self.conn.prepare_cached("INSERT INTO page_references VALUES (?, ?, ?, ?)").unwrap();
 
-        let mut params = Vec::with_capacity(self.batch_size * 3);
+        let mut params = Vec::with_capacity(self.batch_size * 4);
         let (batchable, non_batchable) = self.to_insert.split_at(
             self.to_insert.len() - (self.to_insert.len() % self.batch_size)
         );
 
         let mut count = 0;
         for data in batchable {
-            params.push(&data.0 as &dyn ToSql);
-            params.push(&data.1 as &dyn ToSql);
-            params.push(&data.2 as &dyn ToSql);
+            params.push(&data.title as &dyn ToSql);
+            params.push(&data.links as &dyn ToSql);
+            params.push(&data.is_redirect as &dyn ToSql);
+            params.push(&data.namespace as &dyn ToSql);
             count += 1;
             if count == self.batch_size {
                 if let Err(e) = cached_statement.execute(&*params) {
                     println!("Database batch failed due to error - retrying one at a time: {:?}", e);
 
-                    for params in params.chunks(3) {
+                    for params in params.chunks(4) {
                         if let Err(e) = individual_cached_statement.execute(params) {
                             let title = match params[0].to_sql().unwrap()
                             {
@@ -224,127 +805,544 @@ impl DB {
                             };
 
                             println!(
-                                "Database insert on data [{}, {}, {:?}] failed due to error: {:?}",
+                                "Database insert on data [{}, {}, {:?}, {:?}] failed due to error: {:?}",
                                 title,
                                 links,
                                 params[2].to_sql().unwrap(),
+                                params[3].to_sql().unwrap(),
                                 e
                             );
 
-                            let result = self.conn.execute("INSERT INTO page_reference_errors VALUES (?, ?, ?)", params);
-                            if result.is_err() { println!("{:?}", result.unwrap_err()); }
+                            let cause = e.to_string();
+                            let mut params = params.to_vec();
+                            params.push(&cause);
+                            let result = self.conn.execute("INSERT INTO page_reference_errors VALUES (?, ?, ?, ?, ?)", &*params);
+                            if let Err(e) = result { println!("{:?}", e); }
                         }
                     }
                 }
-                params = Vec::with_capacity(self.batch_size * 3);
+                params = Vec::with_capacity(self.batch_size * 4);
                 count = 0;
             }
         }
 
-        if non_batchable.len() > 0 {
+        if !non_batchable.is_empty() {
             for data in non_batchable {
-                if let Err(e) = individual_cached_statement.execute((&data.0, &data.1, &data.2)) {
+                let data = (&data.title, &data.links, &data.is_redirect, &data.namespace);
+                if let Err(e) = individual_cached_statement.execute(data) {
                     println!(
-                        "Database insert on data [{:?}, {:?}, {:?}] failed due to error: {:?}",
+                        "Database insert on data [{:?}, {:?}, {:?}, {:?}] failed due to error: {:?}",
                         data.0,
                         data.1,
                         data.2,
+                        data.3,
                         e
                     );
 
-                    let result = self.conn.execute("INSERT INTO page_reference_errors VALUES (?, ?, ?)", (&data.0, &data.1, &data.2));
-                    if result.is_err() { println!("{:?}", result.unwrap_err()); }
+                    let result = self.conn.execute("INSERT INTO page_reference_errors VALUES (?, ?, ?, ?, ?)", (data.0, data.1, data.2, data.3, e.to_string()));
+                    if let Err(e) = result { println!("{:?}", e); }
                 }
             }
         }
 
+        drop(cached_statement);
+        drop(individual_cached_statement);
+        self.save_checkpoint(checkpoint);
+        self.conn.execute("COMMIT", ()).unwrap();
+
         self.to_insert = Vec::with_capacity(self.insert_threshold);
         println!("Finished writing to database in {:?}", start.elapsed());
     }
 
-    pub fn cache(&mut self, title: String, links: String, is_redirect: bool) {
-        self.to_insert.push((title, links, is_redirect));
-        if self.to_insert.len() >= self.insert_threshold {
-            self.write_to_db();
-        }
+    pub fn cache(&mut self, page: ParsedPage) {
+        self.to_insert.push(page);
     }
 }
 
-const REDIRECT_TEXT: &str = "#REDIRECT [[";
-const FORBIDDEN_PATTERNS: [&str; 10] = [
-    "Wikipedia:",
-    "Category:",
-    "File:",
-    "Special:",
-    "Template:",
-    "Template_talk:",
-    "User:",
-    "WP:",
-    "Help:",
-    "File:",
-    // "Portal:",
+const DEFAULT_REDIRECT_WORD: &str = "#REDIRECT";
+
+struct Links {
+    links: String,
+    is_redirect: bool,
+    /// Whether any target contained XML or HTML entities
+    decoded_entities: bool,
+}
+
+/// Characters the dump escapes as entities, so any target containing them was decoded
+const ESCAPED_CHARS: [char; 4] = ['&', '<', '>', '"'];
+
+/// Decodes the HTML entities MediaWiki accepts inside link targets, e.g. `[[AT&amp;amp;T]]`,
+/// after the XML entities of the whole body have already been decoded
+fn decode_target<'a>(target: &'a str, decoded: &mut bool) -> Cow<'a, str> {
+    if target.contains(ESCAPED_CHARS) {
+        *decoded = true;
+    }
+
+    let target = decode_entities(target);
+    if let Cow::Owned(_) = target {
+        *decoded = true;
+    }
+    target
+}
+
+/// Sections skipped unless `--include-sections` or `--exclude-sections` is given
+const DEFAULT_EXCLUDED_SECTIONS: [&str; 9] = [
+    "See also", "References", "External links", "Further reading", "Notes",
+    "Footnotes", "Citations", "Sources", "Bibliography",
 ];
 
-const SEE_ALSO: &str = "==See also==";
-const REFERENCES: &str = "==References==";
-fn get_links_from_body(body: String, title: &String) -> Result<(String, bool), String> {
-    return if body.len() > REDIRECT_TEXT.len() && body.is_char_boundary(REDIRECT_TEXT.len()) && &body[..REDIRECT_TEXT.len()] == REDIRECT_TEXT {
-        let end = body.find("]]");
-        if let Some(end) = end {
-            let redirect = body[REDIRECT_TEXT.len()..end].trim();
-            let redirect = redirect.split('#').nth(0).unwrap().trim();
-            for pattern in FORBIDDEN_PATTERNS {
-                if redirect.len() >= pattern.len() && redirect.is_char_boundary(pattern.len()) && &redirect[..pattern.len()] == pattern {
-                    return Ok(("".to_string(), true));
-                }
+/// Decodes and normalizes a link target, returning `None` if it is dropped
+fn link_target(target: &str, context: &Context, decoded_entities: &mut bool) -> Option<String> {
+    let mut page_decoded = *decoded_entities;
+    let link = decode_target(target.trim(), &mut page_decoded);
+    let mut link = link.as_ref();
+    if !context.keeps_target(link) {
+        return None;
+    }
+
+    if let Some(pos) = link.find('#') {
+        if pos == 0 {
+            return None;
+        }
+        link = &link[..pos];
+    }
+
+    let link = normalize(link, &context.namespaces);
+    if link.is_empty() {
+        return None;
+    }
+
+    *decoded_entities = page_decoded;
+    Some(link)
+}
+
+/// `redirect` is the still-escaped `title` of the page's `<redirect>` element, if it has one.
+/// Otherwise the body is checked for a redirect magic word.
+fn get_links_from_body(body: String, redirect: Option<String>, title: &String, context: &Context) -> Result<Links, String> {
+    let mut decoded_entities = false;
+
+    let redirect = match &redirect {
+        Some(target) => Some(Redirect::Target(target.as_str())),
+        None => parse_redirect(&body, &context.settings.redirect_words)
+    };
+
+    if let Some(redirect) = redirect {
+        if let Redirect::Target(redirect) = redirect {
+            let redirect = decode_target(redirect.trim(), &mut decoded_entities);
+            let redirect = redirect.split('#').next().unwrap().trim();
+            // Redirects between templates are followed by `resolve_template_redirects` even when
+            // the template namespace isn't kept
+            let target_namespace = context.namespaces.namespace_of(redirect);
+            let between_templates = target_namespace == TEMPLATE_NAMESPACE && context.namespaces.namespace_of(title) == TEMPLATE_NAMESPACE;
+            if !context.keeps(target_namespace) && !between_templates {
+                return Ok(Links { links: "".to_string(), is_redirect: true, decoded_entities });
             }
-            Ok((redirect.to_string(), true))
+            Ok(Links { links: normalize(redirect, &context.namespaces), is_redirect: true, decoded_entities })
         } else {
             Err(format!("Getting redirect link from '{}' failed", title))
         }
     } else {
-        let mut references: String = String::new();
+        let mut links: Vec<String> = Vec::new();
 
-        let mut first = true;
-        let limit = body.find(SEE_ALSO).or_else(|| body.find(REFERENCES)).unwrap_or(body.len());
-        let body = &body[..limit];
-        'link_search_loop: for (link_pos, _) in body.match_indices("[[") {
-            let after_link_start = &body[link_pos + "[[".len()..];
+        let body = mask_hidden(&body, context.settings.include_ref_links);
+        let sections = context.settings.sections.kept_ranges(&body);
+        let links_in_sections = sections.iter().flat_map(|section| {
+            body[section.clone()].match_indices("[[").map(move |(pos, _)| (section, section.start + pos))
+        });
+        for (section, link_pos) in links_in_sections {
+            let after_link_start = &body[link_pos + "[[".len()..section.end];
             let end1 = after_link_start.find('|');
             let end2 = after_link_start.find(']');
-            let end = if end1.is_some() && end2.is_some() {
-                Some(min(end1.unwrap(), end2.unwrap()))
+            let end = if let (Some(end1), Some(end2)) = (end1, end2) {
+                Some(min(end1, end2))
             }
             else {
-                end1.or_else(|| end2)
+                end1.or(end2)
             };
 
             if let Some(end) = end {
-                let mut link = after_link_start[..end].trim();
-                for pattern in FORBIDDEN_PATTERNS {
-                    if link.len() >= pattern.len() && link.is_char_boundary(pattern.len()) && &link[..pattern.len()] == pattern {
-                        continue 'link_search_loop;
-                    }
+                // Targets built from template parameters can't be resolved without expanding them
+                if after_link_start[..end].contains('{') {
+                    continue;
                 }
 
-                if let Some(pos) = link.find('#') {
-                    if pos == 0 {
+                if let Some(link) = link_target(&after_link_start[..end], context, &mut decoded_entities) {
+                    links.push(link);
+                }
+            } else {
+                break;
+            }
+        }
+
+        if context.settings.templates {
+            let mut seen: HashSet<String> = links.iter().cloned().collect();
+            for transclusion in transclusions(&body) {
+                let name = normalize(transclusion.name, &context.namespaces);
+                if HATNOTE_TEMPLATES.contains(&name.as_str()) {
+                    if !sections.iter().any(|section| section.contains(&transclusion.position)) {
                         continue;
                     }
-                    link = &link[..pos];
+
+                    // Named arguments like `l1=` are labels, not targets
+                    for arg in transclusion.args.iter().filter(|arg| !arg.contains(['=', '{'])) {
+                        if let Some(link) = link_target(arg, context, &mut decoded_entities) {
+                            if seen.insert(link.clone()) {
+                                links.push(link);
+                            }
+                        }
+                    }
+                    continue;
                 }
 
-                if first {
-                    first = false;
-                } else {
-                    references += "<|>";
+                // `{{:Foo}}` transcludes a page from the main namespace, anything else a template
+                if name.is_empty() || transclusion.name.trim_start().starts_with(':') {
+                    continue;
+                }
+                // `{{Foo}}` and `{{Template:Foo}}` are the same template
+                let template = match context.namespaces.namespace_of(&name) {
+                    0 => format!("{}:{}", context.namespaces.name(TEMPLATE_NAMESPACE).unwrap(), name),
+                    _ => name
+                };
+                let Some(template_links) = context.templates.get(&template) else { continue; };
+                for link in template_links.split("<|>").filter(|link| !link.is_empty()) {
+                    if link != title && seen.insert(link.to_string()) {
+                        links.push(link.to_string());
+                    }
                 }
-                references += link;
-            } else {
-                break;
             }
         }
 
-        Ok((references, false))
+        let references = links.join("<|>");
+        Ok(Links { links: references, is_redirect: false, decoded_entities })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SITEINFO: &str = r#"
+      <namespace key="0" case="first-letter" />
+      <namespace key="2" case="first-letter">User</namespace>
+      <namespace key="4" case="first-letter">Wikipedia</namespace>
+      <namespace key="6" case="first-letter">File</namespace>
+      <namespace key="10" case="first-letter">Template</namespace>
+      <namespace key="14" case="first-letter">Category</namespace>"#;
+
+    fn context_keeping(namespaces: &[i32]) -> Context {
+        Context {
+            settings: Settings {
+                dump_path: String::new(),
+                index_path: String::new(),
+                threads: 1,
+                namespaces: namespaces.iter().copied().collect(),
+                redirect_words: vec![DEFAULT_REDIRECT_WORD.to_string()],
+                include_ref_links: false,
+                templates: true,
+                sections: SectionPolicy::exclude(&DEFAULT_EXCLUDED_SECTIONS),
+                resume: false,
+            },
+            namespaces: Namespaces::from_siteinfo(SITEINFO),
+            templates: HashMap::new(),
+        }
+    }
+
+    /// A context that knows the links of the templates in `pages`, read the way `main` reads them
+    fn context_with_templates(pages: &[String]) -> Context {
+        let mut context = context_keeping(&[0]);
+        let templates = parse_template_block(pages.concat().as_bytes(), &context);
+        context.templates = resolve_template_redirects(templates);
+        context
+    }
+
+    /// A `<page>` laid out like the dump, with a self-closing `<text />` when `text` is `None`
+    fn page_in(namespace: i32, title: &str, text: Option<&str>) -> String {
+        let text = match text {
+            Some(text) => format!("      <text bytes=\"{}\" xml:space=\"preserve\">{}</text>", text.len(), text),
+            None => "      <text bytes=\"0\" />".to_string()
+        };
+        format!("  <page>\n    <title>{}</title>\n    <ns>{}</ns>\n    <id>1</id>\n    <revision>\n      <id>2</id>\n{}\n    </revision>\n  </page>\n", title, namespace, text)
+    }
+
+    fn page(title: &str, text: Option<&str>) -> String {
+        page_in(0, title, text)
+    }
+
+    /// A page with a `<redirect>` element, whose `title` is escaped like the rest of the XML
+    fn redirect_page(title: &str, target: &str, text: &str) -> String {
+        page(title, Some(text)).replace("    <revision>", &format!("    <redirect title=\"{}\" />\n    <revision>", target))
+    }
+
+    /// Parses the pages as one stream, as `title: links` or `title => target` for redirects
+    fn parse_with(context: &Context, pages: &[String]) -> Vec<String> {
+        parse_block(pages.concat().as_bytes(), context).into_iter().map(|page| {
+            format!("{}{}{}", page.title, if page.is_redirect { " => " } else { ": " }, page.links)
+        }).collect()
+    }
+
+    fn parse(pages: &[String]) -> Vec<String> {
+        parse_with(&context_keeping(&[0]), pages)
+    }
+
+    fn links(body: &str) -> (String, bool) {
+        let links = get_links_from_body(body.to_string(), None, &"Test".to_string(), &context_keeping(&[0])).unwrap();
+        (links.links, links.is_redirect)
+    }
+
+    #[test]
+    fn pages_come_out_in_order() {
+        let pages = parse(&[
+            page("Bedford", Some("Town on the [[River Great Ouse|Ouse]].\nNear [[Luton]] and [[Milton Keynes#Transport]].")),
+            page("Luton", Some("[[Bedford]]")),
+        ]);
+        assert_eq!(pages, ["Bedford: River Great Ouse<|>Luton<|>Milton Keynes", "Luton: Bedford"]);
+    }
+
+    #[test]
+    fn empty_pages_do_not_swallow_the_next_one() {
+        let pages = parse(&[page("Empty", None), page("Next", Some("[[A]]"))]);
+        assert_eq!(pages, ["Empty: ", "Next: A"]);
+    }
+
+    #[test]
+    fn pages_cut_off_by_the_stream_are_dropped() {
+        let cut = "  <page>\n    <title>Cut</title>\n    <ns>0</ns>\n      <text bytes=\"9\" xml:space=\"preserve\">[[B]]\n".to_string();
+        assert_eq!(parse(&[page("Whole", Some("[[A]]")), cut]), ["Whole: A"]);
+    }
+
+    #[test]
+    fn redirects_keep_only_their_target() {
+        assert_eq!(parse(&[page("Old", Some("#REDIRECT [[New#History]]"))]), ["Old => New"]);
+        let context = context_keeping(&[0]);
+        assert!(get_links_from_body("#REDIRECT [[New".to_string(), None, &"Old".to_string(), &context).is_err());
+    }
+
+    #[test]
+    fn redirect_magic_words_in_any_case() {
+        assert_eq!(links("  #redirect[[New]]"), ("New".to_string(), true));
+        assert_eq!(links("#Redirect: [[New|new]]"), ("New".to_string(), true));
+        assert_eq!(links("#WEITERLEITUNG [[New]]"), ("New".to_string(), false));
+
+        let mut context = context_keeping(&[0]);
+        context.settings.redirect_words.push("#WEITERLEITUNG".to_string());
+        assert_eq!(parse_with(&context, &[page("Alt", Some("#weiterleitung [[Neu]]"))]), ["Alt => Neu"]);
+    }
+
+    #[test]
+    fn redirect_elements_win_over_the_body() {
+        let pages = parse(&[
+            redirect_page("Old", "New &amp; improved", "#REDIRECT [[Something else]]"),
+            redirect_page("Odd", "Target", "Not a redirect [[Elsewhere]]"),
+        ]);
+        assert_eq!(pages, ["Old => New & improved", "Odd => Target"]);
+    }
+
+    #[test]
+    fn pages_with_a_bad_namespace_are_skipped() {
+        let odd = page("Odd", Some("[[A]]")).replace("<ns>0</ns>", "<ns>main</ns>");
+        assert_eq!(parse(&[odd, page("Next", Some("[[B]]"))]), ["Next: B"]);
+    }
+
+    #[test]
+    fn entities_are_decoded_in_titles_and_links() {
+        let context = context_keeping(&[0]);
+        let xml = page("AT&amp;T", Some("[[Procter &amp;amp; Gamble]] [[Bell]] [[X &amp;lt; y]]")) + &page("Bell", Some("[[AT&amp;T]]")) + &page("Plain", Some("[[Bell]]"));
+        let pages = parse_block(xml.as_bytes(), &context);
+        let pages: Vec<_> = pages.iter().map(|p| (p.title.as_str(), p.links.as_str(), p.decoded_title, p.decoded_links)).collect();
+        assert_eq!(pages, [
+            ("AT&T", "Procter & Gamble<|>Bell<|>X < y", true, true),
+            ("Bell", "AT&T", false, true),
+            ("Plain", "Bell", false, false),
+        ]);
+    }
+
+    #[test]
+    fn titles_and_targets_are_normalized() {
+        let pages = parse_with(&context_keeping(&[0, 14]), &[
+            page("bedford", Some("[[river_great  ouse|Ouse]] [[ luton ]] [[category:towns]] [[ _ ]]")),
+            page("Old_name", Some("#REDIRECT [[new_name]]")),
+        ]);
+        assert_eq!(pages, ["Bedford: River great ouse<|>Luton<|>Category:Towns", "Old name => New name"]);
+    }
+
+    #[test]
+    fn links_into_other_namespaces_are_dropped() {
+        assert_eq!(links("[[A]] [[Category:B]] [[:category:B]] [[File:C.jpg|thumb]] [[WP:D]] [[#Local]] [[E]]"), ("A<|>E".to_string(), false));
+        assert_eq!(links("[[Not a namespace:F]]"), ("Not a namespace:F".to_string(), false));
+        assert_eq!(links("#REDIRECT [[User:Someone]]"), ("".to_string(), true));
+    }
+
+    #[test]
+    fn pages_are_kept_by_namespace_id() {
+        let pages = [page_in(14, "Category:Towns", Some("[[Bedford]] [[Category:Places]]")), page("Bedford", Some("[[Category:Towns]]"))];
+        assert_eq!(parse(&pages), ["Bedford: "]);
+        assert_eq!(parse_with(&context_keeping(&[0, 14]), &pages), ["Category:Towns: Bedford<|>Category:Places", "Bedford: Category:Towns"]);
+    }
+
+    #[test]
+    fn hidden_links_are_skipped() {
+        let body = "[[A]] <!-- [[B]] --> <nowiki>[[C]]</nowiki> <ref>[[D]]</ref> [[E]]";
+        assert_eq!(links(body), ("A<|>E".to_string(), false));
+
+        let mut context = context_keeping(&[0]);
+        context.settings.include_ref_links = true;
+        let links = get_links_from_body(body.to_string(), None, &"Test".to_string(), &context).unwrap();
+        assert_eq!(links.links, "A<|>D<|>E");
+    }
+
+    #[test]
+    fn scanning_keeps_pages_escaped() {
+        let xml = page_in(10, "Template:A&amp;B", Some("[[X]] &lt;noinclude&gt;")) + &page("Main", Some("[[Y]]"))
+            + &redirect_page("Template:Old", "Template:A&amp;B", "#REDIRECT [[Template:A&amp;B]]").replace("<ns>0</ns>", "<ns>10</ns>");
+
+        let mut scanned = Vec::new();
+        scan_block(xml.as_bytes(), |ns| ns == TEMPLATE_NAMESPACE, |page| {
+            scanned.push((page.title, page.namespace, page.redirect, page.body));
+        });
+        assert_eq!(scanned, [
+            ("Template:A&amp;B".to_string(), 10, None, "[[X]] &lt;noinclude&gt;".to_string()),
+            ("Template:Old".to_string(), 10, Some("Template:A&amp;B".to_string()), "#REDIRECT [[Template:A&amp;B]]".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn template_redirects_are_followed() {
+        let template = |title: &str, links: &str, is_redirect| TemplatePage { title: title.to_string(), links: links.to_string(), is_redirect };
+        let resolved = resolve_template_redirects(vec![
+            template("Template:Bedford navbox", "Bedford<|>Luton", false),
+            template("Template:Bedfordshire", "Template:Bedford navbox", true),
+            template("Template:Beds", "Template:Bedfordshire", true),
+            template("Template:Loop A", "Template:Loop B", true),
+            template("Template:Loop B", "Template:Loop A", true),
+            template("Template:Broken", "Template:Missing", true),
+        ]);
+
+        let mut resolved: Vec<_> = resolved.iter().map(|(title, links)| format!("{}: {}", title, links)).collect();
+        resolved.sort();
+        assert_eq!(resolved, [
+            "Template:Bedford navbox: Bedford<|>Luton",
+            "Template:Bedfordshire: Bedford<|>Luton",
+            "Template:Beds: Bedford<|>Luton",
+        ]);
+    }
+
+    #[test]
+    fn templates_add_their_links_once() {
+        let context = context_with_templates(&[
+            page_in(10, "Template:Bedford navbox", Some("[[Bedford]] [[Luton]] [[Dunstable]]&lt;noinclude&gt;[[Navboxes]]&lt;/noinclude&gt;")),
+        ]);
+        let pages = parse_with(&context, &[
+            page("Bedford", Some("[[Luton]] {{bedford_navbox}} {{Unknown}}")),
+            page("Sandy", Some("{{:Bedford navbox}} [[Biggleswade]]")),
+        ]);
+        assert_eq!(pages, ["Bedford: Luton<|>Dunstable", "Sandy: Biggleswade"]);
+    }
+
+    #[test]
+    fn templates_are_found_with_or_without_their_prefix() {
+        let context = context_with_templates(&[
+            page_in(10, "Template:Bedford navbox", Some("[[Luton]] [[Dunstable]]")),
+            page_in(10, "Template:Bedfordshire", Some("#REDIRECT [[Template:Bedford navbox]]")),
+        ]);
+        let pages = parse_with(&context, &[
+            page("A", Some("{{Template:Bedford navbox}}")),
+            page("B", Some("{{template:bedford_navbox}}")),
+            page("C", Some("{{Bedfordshire}}")),
+            page("D", Some("{{User:Bedford navbox}}")),
+        ]);
+        assert_eq!(pages, ["A: Luton<|>Dunstable", "B: Luton<|>Dunstable", "C: Luton<|>Dunstable", "D: "]);
+
+        // Only redirects from one template to another are kept outside the kept namespaces
+        assert_eq!(parse(&[page("Old", Some("#REDIRECT [[Template:Bedford navbox]]"))]), ["Old => "]);
+    }
+
+    #[test]
+    fn hatnotes_link_their_arguments() {
+        let pages = parse(&[page("Bedford", Some(
+            "{{Main|History of Bedford}} {{See also|Luton|Dunstable|l1=Luton town}} [[Luton]] {{Further|{{{1}}}}}\n==See also==\n{{Main|Sandy}}"
+        ))]);
+        assert_eq!(pages, ["Bedford: Luton<|>History of Bedford<|>Dunstable"]);
+
+        let mut context = context_with_templates(&[page_in(10, "Template:Bedford navbox", Some("[[Luton]]"))]);
+        context.settings.templates = false;
+        assert_eq!(parse_with(&context, &[page("Bedford", Some("{{Main|History}} {{Bedford navbox}} [[A]]"))]), ["Bedford: A"]);
+    }
+
+    #[test]
+    fn links_in_excluded_sections_are_skipped() {
+        let body = "[[A]]\n== See also ==\n[[B]]\n== History ==\n[[C]] {{Main|D}}\n==External_links==\n[[E]] {{Main|F}}";
+        assert_eq!(links(body), ("A<|>C<|>D".to_string(), false));
+
+        let mut context = context_keeping(&[0]);
+        context.settings.sections = SectionPolicy::include(&["External links"]);
+        let links = get_links_from_body(body.to_string(), None, &"Test".to_string(), &context).unwrap();
+        assert_eq!(links.links, "A<|>E<|>F");
+    }
+
+    fn parsed(title: &str, links: &str) -> ParsedPage {
+        ParsedPage { title: title.to_string(), namespace: 0, links: links.to_string(), is_redirect: false, decoded_title: false, decoded_links: false }
+    }
+
+    fn titles(db: &DB) -> Vec<String> {
+        let mut statement = db.conn.prepare("SELECT title FROM page_references ORDER BY rowid").unwrap();
+        statement.query_map((), |row| row.get(0)).unwrap().map(|title| title.unwrap()).collect()
+    }
+
+    #[test]
+    fn checkpoints_are_kept_only_when_resuming() {
+        let mut db = DB::with_connection(Connection::open_in_memory().unwrap(), 2, 10, false);
+        assert!(db.load_checkpoint().is_none());
+
+        for title in ["A", "B", "C"] {
+            db.cache(parsed(title, ""));
+        }
+        let stats = Stats { pages: 3, decoded_titles: 1, decoded_links: 2 };
+        db.write_to_db(&Checkpoint { next_block: 2, offset: 600, stats, last_title: Some("C".to_string()) });
+
+        let db = DB::with_connection(db.conn, 2, 10, true);
+        let checkpoint = db.load_checkpoint().unwrap();
+        assert_eq!((checkpoint.next_block, checkpoint.offset, checkpoint.last_title.as_deref()), (2, 600, Some("C")));
+        assert_eq!((checkpoint.stats.pages, checkpoint.stats.decoded_titles, checkpoint.stats.decoded_links), (3, 1, 2));
+        assert_eq!(titles(&db), ["A", "B", "C"]);
+        build_link_tables(&db.conn).unwrap();
+
+        let db = DB::with_connection(db.conn, 2, 10, false);
+        assert!(db.load_checkpoint().is_none());
+        assert!(titles(&db).is_empty());
+        assert!(!has_table(&db.conn, "pages"));
+    }
+
+    #[test]
+    fn fresh_runs_forget_the_old_meta() {
+        let db = DB::with_connection(Connection::open_in_memory().unwrap(), 2, 10, false);
+        meta::set(&db.conn, "section_policy", "include: history");
+
+        let db = DB::with_connection(db.conn, 2, 10, true);
+        assert_eq!(meta::get(&db.conn, "section_policy").as_deref(), Some("include: history"));
+
+        let db = DB::with_connection(db.conn, 2, 10, false);
+        assert_eq!(meta::get(&db.conn, "section_policy"), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn resumed_runs_continue_from_the_checkpoint() {
+        let db = DB::with_connection(Connection::open_in_memory().unwrap(), 2, 2, false);
+        let blocks = vec![Block { offset: 100, len: 50 }, Block { offset: 150, len: 50 }, Block { offset: 200, len: 50 }];
+        let checkpoint = Checkpoint { next_block: 1, offset: 150, stats: Stats { pages: 2, ..Stats::default() }, last_title: Some("B".to_string()) };
+
+        // The remaining blocks arrive out of order from the workers
+        let (sender, receiver) = mpsc::sync_channel(2);
+        sender.send((1, vec![parsed("E", "")])).unwrap();
+        sender.send((0, vec![parsed("C", "E"), parsed("D", "")])).unwrap();
+        drop(sender);
+
+        let (db, stats) = write_pages(db, receiver, blocks, checkpoint, Instant::now());
+        assert_eq!(stats.pages, 5);
+
+        let db = DB::with_connection(db.conn, 2, 2, true);
+        let checkpoint = db.load_checkpoint().unwrap();
+        assert_eq!((checkpoint.next_block, checkpoint.offset, checkpoint.last_title.as_deref()), (3, 250, Some("E")));
+        assert_eq!(checkpoint.stats.pages, 5);
+        assert_eq!(titles(&db), ["C", "D", "E"]);
+    }
+}
@@ -1,15 +1,13 @@
-use std::cmp::min;
-use std::fs;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Seek, Write};
-use std::path::Path;
 use std::time::Instant;
+use hhmmss::Hhmmss;
 use rusqlite::Connection;
-use xml::common::Position;
+use wiki_3::meta;
 use xml::{EventReader, ParserConfig};
-use xml::reader::{ParserConfig2, XmlEvent};
+use xml::reader::XmlEvent;
 
 
+#[allow(dead_code)]
 fn print_xml(event: XmlEvent) {
     match event {
         XmlEvent::StartDocument { .. } => { println!("Start document"); }
@@ -17,7 +15,7 @@ fn print_xml(event: XmlEvent) {
         XmlEvent::ProcessingInstruction { .. } => { println!("Processing instruction") }
         XmlEvent::StartElement {
             name,
-            namespace,
+            namespace: _,
             attributes
 
         } => {
@@ -34,23 +32,30 @@ fn print_xml(event: XmlEvent) {
     }
 }
 
+#[allow(dead_code)]
 fn next_until_element(element_name: &str, event_reader: &mut EventReader<File>) -> XmlEvent {
-    let mut ret = XmlEvent::EndDocument;
     loop {
-        ret = event_reader.next().unwrap();
-        match &ret {
-            XmlEvent::StartElement { name, attributes: _, namespace: _ } => {
-                if name.local_name.as_str() == element_name {
-                    break;
-                }
+        let ret = event_reader.next().unwrap();
+        if let XmlEvent::StartElement { name, attributes: _, namespace: _ } = &ret {
+            if name.local_name.as_str() == element_name {
+                return ret;
             }
-            _ => {}
-        };
+        }
     }
-    ret
 }
 
 const TOTAL_ARTICLES: u32 = 23_100_000;
+const DUMP: &str = "enwiki-20230801-pages-articles.xml";
+
+const FORBIDDEN_PATTERNS: [&str; 7] = [
+    "Wikipedia:",
+    "Category:",
+    "File:",
+    "Special:",
+    "Template:",
+    "Template_talk:",
+    "User:"
+];
 
 fn main() {
     // fs::create_dir_all("data").unwrap();
@@ -65,7 +70,16 @@ fn main() {
         ()
     ).unwrap();
 
-    let file = File::open("enwiki-20230801-pages-articles.xml").unwrap();
+    // Tools built around the id tables refuse this database, see `meta::check_schema`
+    meta::set(&conn, "parser", meta::SLOW_PARSER);
+    meta::set(&conn, "parser_version", env!("CARGO_PKG_VERSION"));
+    meta::set(&conn, "dump_file", DUMP);
+    if let Some(date) = meta::dump_date(DUMP) {
+        meta::set(&conn, "dump_date", date);
+    }
+    meta::set(&conn, "forbidden_patterns", FORBIDDEN_PATTERNS.join(","));
+
+    let file = File::open(DUMP).unwrap();
     let parser_config = ParserConfig::new().trim_whitespace(true);
 
     let mut parser = EventReader::new_with_config(file, parser_config);
@@ -77,6 +91,8 @@ fn main() {
     parser.next().unwrap(); // Page
 
     let mut count = 0;
+    let mut links: u64 = 0;
+    let mut redirects: u64 = 0;
     let start = Instant::now();
 
     let mut insert_statement = conn.prepare("INSERT INTO page_references VALUES (?1, ?2, FALSE)").unwrap();
@@ -170,6 +186,7 @@ fn main() {
                 if let Err(e) = insert_redirect_statement.execute((&title, &redirect)) {
                     println!("Redirect insert [{}]->[{}] failed with error {:?}", title, redirect, e);
                 }
+                redirects += 1;
             }
             else {
                 println!("Getting redirect link from '{}' failed", title);
@@ -178,16 +195,6 @@ fn main() {
         else {
             let mut references: Vec<String> = Vec::new();
 
-            const FORBIDDEN_PATTERNS: [&str; 7] = [
-                "Wikipedia:",
-                "Category:",
-                "File:",
-                "Special:",
-                "Template:",
-                "Template_talk:",
-                "User:"
-            ];
-
             const SEE_ALSO: &str = "==See also==";
             const REFERENCES: &str = "==References==";
 
@@ -202,7 +209,7 @@ fn main() {
 
                         if link_depth == 0 {
                             buffer = buffer.chars().take(buffer.len() - 1).collect();
-                            buffer = buffer.split('|').nth(0).unwrap().to_string();
+                            buffer = buffer.split('|').next().unwrap().to_string();
 
                             let mut failed = false;
                             for pattern in FORBIDDEN_PATTERNS {
@@ -237,6 +244,7 @@ fn main() {
                 ) {
                     println!("Link insert [{}]->[{}] failed with error {:?}", title, link, e);
                 }
+                links += 1;
             }
         }
 
@@ -254,12 +262,9 @@ fn main() {
             };
         }
 
-        match parser.next().unwrap() {
-            XmlEvent::StartElement { name, namespace: _, attributes: _ } => {
-                if name.local_name != "page" { break 'main_loop; }
-            }
-            _ => {}
-        };
+        if let XmlEvent::StartElement { name, namespace: _, attributes: _ } = parser.next().unwrap() {
+            if name.local_name != "page" { break 'main_loop; }
+        }
 
         count += 1;
         if count % 10 == 0 {
@@ -268,5 +273,9 @@ fn main() {
 
     }
 
+    meta::set(&conn, "pages", count);
+    meta::set(&conn, "links", links);
+    meta::set(&conn, "redirects", redirects);
+    meta::set(&conn, "build_duration", start.elapsed().hhmmss());
     println!("Finished in {:?}", start.elapsed());
 }
\ No newline at end of file
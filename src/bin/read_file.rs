@@ -8,7 +8,7 @@ fn main() {
     let mut i = i32::MAX;
     while i > 0 {
         let mut buffer = Vec::new();
-        reader.read_until('\n' as u8, &mut buffer).unwrap();
+        reader.read_until(b'\n', &mut buffer).unwrap();
         let string = String::from_utf8(buffer).unwrap();
         if string.contains("Albrecht Achilles") {
             i = 20;
@@ -16,5 +16,4 @@ fn main() {
         print!("{}", string);
         i -= 1;
     }
-    return;
 }
\ No newline at end of file
@@ -0,0 +1,79 @@
+use std::collections::HashSet;
+use std::env;
+use wiki_3::graph::{LinkGraph, PageId, SqliteGraph};
+use wiki_3::meta;
+use wiki_3::namespaces::Namespaces;
+use wiki_3::title::normalize;
+
+/// Lists the pages linking to a title, like MediaWiki's Special:WhatLinksHere.
+/// With `--via-redirects`, pages linking to a redirect to the title are listed too.
+fn main() {
+    let mut title = None;
+    let mut via_redirects = false;
+    let mut db_path = "completed-table.db".to_string();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--via-redirects" => via_redirects = true,
+            "--db" => db_path = args.next().expect("--db needs a path"),
+            _ => title = Some(arg)
+        }
+    }
+    let Some(title) = title else {
+        println!("Usage: what_links_here <title> [--via-redirects] [--db <path>]");
+        return;
+    };
+
+    let conn = meta::open(&db_path).unwrap();
+    let namespaces = Namespaces::load(&conn);
+    let graph = SqliteGraph::new(conn).unwrap();
+
+    let title = normalize(&title, &namespaces);
+    let Some(id) = graph.id(&title) else {
+        println!("There is no page called '{}'", title);
+        return;
+    };
+
+    let mut linking = HashSet::new();
+    let direct: Vec<PageId> = graph.backlinks(id).collect();
+    println!("Pages linking to '{}':", title);
+    for src in &direct {
+        if graph.is_redirect(*src) {
+            println!("  {} (redirect)", graph.title(*src));
+        } else {
+            println!("  {}", graph.title(*src));
+            linking.insert(*src);
+        }
+    }
+
+    if !via_redirects {
+        println!("{} links, {} from pages that aren't redirects", direct.len(), linking.len());
+        return;
+    }
+
+    // Pages reach the title through any redirect whose chain ends there, not just the direct ones
+    let mut redirects: Vec<PageId> = graph.redirects_to(id);
+    let mut seen: HashSet<PageId> = redirects.iter().copied().collect();
+    let mut via = 0;
+    while let Some(redirect) = redirects.pop() {
+        let redirect_title = graph.title(redirect);
+        for src in graph.backlinks(redirect) {
+            if graph.is_redirect(src) {
+                if seen.insert(src) {
+                    redirects.push(src);
+                }
+                continue;
+            }
+
+            println!("  {} (via {})", graph.title(src), redirect_title);
+            via += 1;
+            linking.insert(src);
+        }
+    }
+
+    println!(
+        "{} links, {} through {} redirects, from {} distinct pages",
+        direct.len() + via, via, seen.len(), linking.len()
+    );
+}
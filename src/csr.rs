@@ -0,0 +1,351 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use memmap2::Mmap;
+use rusqlite::Connection;
+use crate::graph::{LinkGraph, PageId};
+
+const MAGIC: &[u8; 8] = b"WIKICSR3";
+const HEADER_LEN: usize = 32;
+const NO_TARGET: u32 = u32::MAX;
+
+/// A link graph in compressed sparse row form, read straight from a memory-mapped file written
+/// by [`write_csr`]. Page ids are the ids of the `pages` table.
+///
+/// Layout, all integers little-endian and every section padded to 8 bytes:
+/// - header: magic, page count (u64), link count (u64), title bytes (u64)
+/// - link offsets: page count + 1 u64s, the links of page `i` are `targets[offsets[i]..offsets[i + 1]]`
+/// - link targets: link count u32s
+/// - backlink offsets and sources: the same two sections for the reversed links, sources in id order
+/// - title offsets: page count + 1 u64s into the title bytes
+/// - title bytes: every title in id order as UTF-8
+/// - title index: page count u32 ids sorted by title, for lookups
+/// - resolved redirect targets: page count u32s, `u32::MAX` for pages that aren't redirects and
+///   for broken or looping redirects
+/// - redirect flags: page count u8s
+pub struct CsrGraph {
+    map: Mmap,
+    pages: usize,
+    offsets: usize,
+    targets: usize,
+    backlink_offsets: usize,
+    backlink_sources: usize,
+    title_offsets: usize,
+    titles: usize,
+    title_index: usize,
+    resolved_targets: usize,
+    redirects: usize,
+}
+
+fn padded(len: usize) -> usize {
+    len.next_multiple_of(8)
+}
+
+impl CsrGraph {
+    pub fn open(path: &str) -> io::Result<CsrGraph> {
+        let file = File::open(path)?;
+        // Safety: the file is only ever written by `write_csr` before it is opened
+        let map = unsafe { Mmap::map(&file)? };
+        if map.len() < HEADER_LEN || &map[..8] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("'{}' is not a CSR graph file", path)));
+        }
+
+        let read_u64 = |at: usize| u64::from_le_bytes(map[at..at + 8].try_into().unwrap()) as usize;
+        let (pages, links, title_bytes) = (read_u64(8), read_u64(16), read_u64(24));
+
+        let offsets = HEADER_LEN;
+        let targets = offsets + padded((pages + 1) * 8);
+        let backlink_offsets = targets + padded(links * 4);
+        let backlink_sources = backlink_offsets + padded((pages + 1) * 8);
+        let title_offsets = backlink_sources + padded(links * 4);
+        let titles = title_offsets + padded((pages + 1) * 8);
+        let title_index = titles + padded(title_bytes);
+        let resolved_targets = title_index + padded(pages * 4);
+        let redirects = resolved_targets + padded(pages * 4);
+        if map.len() < redirects + pages {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("'{}' is truncated", path)));
+        }
+
+        Ok(CsrGraph { map, pages, offsets, targets, backlink_offsets, backlink_sources, title_offsets, titles, title_index, resolved_targets, redirects })
+    }
+
+    fn u64_at(&self, section: usize, i: usize) -> usize {
+        let at = section + i * 8;
+        u64::from_le_bytes(self.map[at..at + 8].try_into().unwrap()) as usize
+    }
+
+    fn u32_at(&self, section: usize, i: usize) -> u32 {
+        let at = section + i * 4;
+        u32::from_le_bytes(self.map[at..at + 4].try_into().unwrap())
+    }
+
+    /// The pages `id` links to, in page order
+    pub fn links(&self, id: u32) -> impl Iterator<Item = u32> + '_ {
+        let (start, end) = (self.u64_at(self.offsets, id as usize), self.u64_at(self.offsets, id as usize + 1));
+        (start..end).map(|i| self.u32_at(self.targets, i))
+    }
+
+    /// The pages that link to `id`, in id order
+    pub fn backlinks(&self, id: u32) -> impl Iterator<Item = u32> + '_ {
+        let (start, end) = (self.u64_at(self.backlink_offsets, id as usize), self.u64_at(self.backlink_offsets, id as usize + 1));
+        (start..end).map(|i| self.u32_at(self.backlink_sources, i))
+    }
+
+    pub fn title(&self, id: u32) -> &str {
+        let (start, end) = (self.u64_at(self.title_offsets, id as usize), self.u64_at(self.title_offsets, id as usize + 1));
+        std::str::from_utf8(&self.map[self.titles + start..self.titles + end]).unwrap()
+    }
+
+    pub fn is_redirect(&self, id: u32) -> bool {
+        self.map[self.redirects + id as usize] != 0
+    }
+
+    pub fn resolved_target(&self, id: u32) -> Option<u32> {
+        Some(self.u32_at(self.resolved_targets, id as usize)).filter(|target| *target != NO_TARGET)
+    }
+
+    /// Finds the id of a normalized title
+    pub fn id(&self, title: &str) -> Option<u32> {
+        let (mut low, mut high) = (0, self.pages);
+        while low < high {
+            let middle = (low + high) / 2;
+            let id = self.u32_at(self.title_index, middle);
+            match self.title(id).cmp(title) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(id)
+            }
+        }
+        None
+    }
+}
+
+/// Tracks how much has been written so sections can be padded
+struct CsrWriter {
+    out: BufWriter<File>,
+    written: usize,
+}
+
+impl CsrWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.written += bytes.len();
+        self.out.write_all(bytes)
+    }
+
+    fn pad(&mut self) -> io::Result<()> {
+        let padding = padded(self.written) - self.written;
+        self.write(&[0; 8][..padding])
+    }
+
+    /// Writes the running totals of `counts`, starting from 0
+    fn write_offsets(&mut self, counts: Vec<u64>) -> io::Result<()> {
+        let mut offset = 0u64;
+        self.write(&offset.to_le_bytes())?;
+        for count in counts {
+            offset += count;
+            self.write(&offset.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Writes the single id column `query` returns as u32s
+    fn write_ids(&mut self, conn: &Connection, query: &str) -> Result<(), Box<dyn Error>> {
+        let mut statement = conn.prepare(query)?;
+        let mut rows = statement.query(())?;
+        while let Some(row) = rows.next()? {
+            self.write(&row.get::<_, u32>(0)?.to_le_bytes())?;
+        }
+        self.pad()?;
+        Ok(())
+    }
+}
+
+/// Reads `(id, count)` rows into a count per page
+fn count_by_page(conn: &Connection, query: &str, pages: usize) -> rusqlite::Result<Vec<u64>> {
+    let mut counts = vec![0u64; pages];
+    let mut statement = conn.prepare(query)?;
+    let mut rows = statement.query(())?;
+    while let Some(row) = rows.next()? {
+        counts[row.get::<_, usize>(0)?] = row.get(1)?;
+    }
+    Ok(counts)
+}
+
+/// Writes the `pages` and `links` tables to `path` in the layout read by [`CsrGraph`].
+/// Page ids must run from 0 without gaps, as [`crate::schema::build_link_tables`] creates them.
+pub fn write_csr(conn: &Connection, path: &str) -> Result<(), Box<dyn Error>> {
+    let pages: usize = conn.query_row("SELECT count(*) FROM pages", (), |row| row.get(0))?;
+    let max_id: Option<usize> = conn.query_row("SELECT max(id) FROM pages", (), |row| row.get(0))?;
+    if max_id.is_some_and(|max_id| max_id + 1 != pages) {
+        return Err("Page ids are not contiguous".into());
+    }
+
+    let link_counts = count_by_page(conn, "SELECT src_id, count(*) FROM links GROUP BY src_id", pages)?;
+    let backlink_counts = count_by_page(conn, "SELECT dst_id, count(*) FROM backlinks GROUP BY dst_id", pages)?;
+    let links: u64 = link_counts.iter().sum();
+    let title_bytes: u64 = conn.query_row("SELECT coalesce(sum(length(CAST(title AS BLOB))), 0) FROM pages", (), |row| row.get(0))?;
+
+    let mut out = CsrWriter { out: BufWriter::new(File::create(path)?), written: 0 };
+    out.write(MAGIC)?;
+    for value in [pages as u64, links, title_bytes] {
+        out.write(&value.to_le_bytes())?;
+    }
+
+    out.write_offsets(link_counts)?;
+    out.write_ids(conn, "SELECT dst_id FROM links ORDER BY src_id, ordinal")?;
+    out.write_offsets(backlink_counts)?;
+    out.write_ids(conn, "SELECT src_id FROM backlinks ORDER BY dst_id, src_id")?;
+
+    let mut redirects = Vec::with_capacity(pages);
+    let mut title_offset = 0u64;
+    out.write(&title_offset.to_le_bytes())?;
+    {
+        let mut statement = conn.prepare("SELECT length(CAST(title AS BLOB)), is_redirect FROM pages ORDER BY id")?;
+        let mut rows = statement.query(())?;
+        while let Some(row) = rows.next()? {
+            title_offset += row.get::<_, u64>(0)?;
+            out.write(&title_offset.to_le_bytes())?;
+            redirects.push(row.get::<_, bool>(1)? as u8);
+        }
+    }
+
+    {
+        let mut statement = conn.prepare("SELECT CAST(title AS BLOB) FROM pages ORDER BY id")?;
+        let mut rows = statement.query(())?;
+        while let Some(row) = rows.next()? {
+            out.write(row.get_ref(0)?.as_bytes()?)?;
+        }
+    }
+    out.pad()?;
+
+    // SQLite's default collation compares bytes, the same order `str::cmp` uses for lookups
+    out.write_ids(conn, "SELECT id FROM pages ORDER BY title")?;
+
+    {
+        let mut statement = conn.prepare("SELECT resolved_target FROM pages ORDER BY id")?;
+        let mut rows = statement.query(())?;
+        while let Some(row) = rows.next()? {
+            out.write(&row.get::<_, Option<u32>>(0)?.unwrap_or(NO_TARGET).to_le_bytes())?;
+        }
+    }
+    out.pad()?;
+
+    out.write(&redirects)?;
+    out.out.flush()?;
+    Ok(())
+}
+
+impl LinkGraph for CsrGraph {
+    fn len(&self) -> usize {
+        self.pages
+    }
+
+    fn id(&self, title: &str) -> Option<PageId> {
+        CsrGraph::id(self, title)
+    }
+
+    fn title(&self, id: PageId) -> String {
+        CsrGraph::title(self, id).to_string()
+    }
+
+    fn is_redirect(&self, id: PageId) -> bool {
+        CsrGraph::is_redirect(self, id)
+    }
+
+    fn resolved_target(&self, id: PageId) -> Option<PageId> {
+        CsrGraph::resolved_target(self, id)
+    }
+
+    fn backlinks(&self, id: PageId) -> impl Iterator<Item = PageId> + '_ {
+        CsrGraph::backlinks(self, id)
+    }
+
+    fn links(&self, id: PageId) -> impl Iterator<Item = PageId> + '_ {
+        CsrGraph::links(self, id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("wiki-3-{}-{}.csr", name, std::process::id())).to_str().unwrap().to_string()
+    }
+
+    /// A database with the tables `build_link_tables` makes from `(title, links, is_redirect)` rows
+    fn database(pages: &[(&str, &str, bool)]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE page_references (title TEXT PRIMARY KEY, links TEXT, is_redirect INTEGER, namespace INTEGER)", ()).unwrap();
+        for (title, links, is_redirect) in pages {
+            conn.execute("INSERT INTO page_references VALUES (?, ?, ?, 0)", (title, links, is_redirect)).unwrap();
+        }
+        crate::schema::build_link_tables(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn graphs_survive_a_round_trip() {
+        let conn = database(&[
+            ("Zürich", "Bern<|>Basel<|>Zug", false),
+            ("Bern", "Zürich", false),
+            ("Old Bern", "Bern", true),
+            ("Basel", "", false),
+            ("Zug", "Zug<|>Zürich", false),
+            ("Old Geneva", "Geneva", true),
+        ]);
+        let path = temp_path("round-trip");
+        write_csr(&conn, &path).unwrap();
+        let graph = CsrGraph::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(graph.len(), 6);
+        let titles: Vec<&str> = (0..6).map(|id| graph.title(id)).collect();
+        assert_eq!(titles, ["Zürich", "Bern", "Old Bern", "Basel", "Zug", "Old Geneva"]);
+        let links: Vec<Vec<u32>> = (0..6).map(|id| graph.links(id).collect()).collect();
+        assert_eq!(links, [vec![1, 3, 4], vec![0], vec![1], vec![], vec![4, 0], vec![]]);
+        let backlinks: Vec<Vec<u32>> = (0..6).map(|id| graph.backlinks(id).collect()).collect();
+        assert_eq!(backlinks, [vec![1, 4], vec![0, 2], vec![], vec![0], vec![0, 4], vec![]]);
+        let redirects: Vec<bool> = (0..6).map(|id| graph.is_redirect(id)).collect();
+        assert_eq!(redirects, [false, false, true, false, false, true]);
+        let resolved: Vec<Option<u32>> = (0..6).map(|id| graph.resolved_target(id)).collect();
+        assert_eq!(resolved, [None, None, Some(1), None, None, None]);
+
+        for (id, title) in titles.iter().enumerate() {
+            assert_eq!(graph.id(title), Some(id as u32));
+        }
+        assert_eq!(graph.id("Geneva"), None);
+        assert_eq!(graph.id("zürich"), None);
+    }
+
+    #[test]
+    fn empty_graphs_survive_a_round_trip() {
+        let path = temp_path("empty");
+        write_csr(&database(&[]), &path).unwrap();
+        let graph = CsrGraph::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(graph.is_empty());
+        assert_eq!(graph.id("Anything"), None);
+    }
+
+    #[test]
+    fn gaps_in_page_ids_are_refused() {
+        let conn = database(&[("A", "", false), ("B", "", false)]);
+        conn.execute("UPDATE pages SET id = 5 WHERE id = 1", ()).unwrap();
+        assert!(write_csr(&conn, &temp_path("gaps")).is_err());
+    }
+
+    #[test]
+    fn other_files_are_refused() {
+        let path = temp_path("other");
+        std::fs::write(&path, b"WIKICSR1 but far too short").unwrap();
+        let truncated = CsrGraph::open(&path);
+        std::fs::write(&path, b"not a graph file, just some text").unwrap();
+        let other = CsrGraph::open(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(truncated.err().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(other.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,93 @@
+use std::borrow::Cow;
+
+/// Named entities MediaWiki decodes in titles, on top of the five XML ones
+const NAMED_ENTITIES: [(&str, char); 10] = [
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("apos", '\''),
+    ("nbsp", '\u{a0}'),
+    ("ndash", '–'),
+    ("mdash", '—'),
+    ("lrm", '\u{200e}'),
+    ("rlm", '\u{200f}'),
+];
+
+/// Longest entity body accepted between `&` and `;`, e.g. `#x10FFFF`
+const MAX_ENTITY_LEN: usize = 8;
+
+/// Decodes named (`&amp;`) and numeric (`&#38;`, `&#x26;`) character references. Unknown or
+/// malformed references are left as they are. Only allocates if something was decoded.
+pub fn decode_entities(text: &str) -> Cow<'_, str> {
+    let Some(first) = text.find('&') else { return Cow::Borrowed(text); };
+
+    let mut decoded = String::with_capacity(text.len());
+    decoded.push_str(&text[..first]);
+
+    let mut rest = &text[first..];
+    let mut changed = false;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        let entity = rest[1..].find(';')
+            .filter(|end| *end <= MAX_ENTITY_LEN)
+            .and_then(|end| decode_entity(&rest[1..end + 1]).map(|c| (c, end + 2)));
+
+        match entity {
+            Some((c, len)) => {
+                decoded.push(c);
+                rest = &rest[len..];
+                changed = true;
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+
+    if changed { Cow::Owned(decoded) } else { Cow::Borrowed(text) }
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    if let Some(number) = entity.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse::<u32>().ok()?
+        };
+        return char::from_u32(code);
+    }
+
+    NAMED_ENTITIES.iter().find(|(name, _)| *name == entity).map(|(_, c)| *c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_entities() {
+        assert_eq!(decode_entities("AT&amp;T"), "AT&T");
+        assert_eq!(decode_entities("&lt;b&gt; &quot;x&quot; &apos;"), "<b> \"x\" '");
+        assert_eq!(decode_entities("1914&ndash;1918&nbsp;&mdash;"), "1914–1918\u{a0}—");
+    }
+
+    #[test]
+    fn numeric_entities() {
+        assert_eq!(decode_entities("&#38;&#x26;&#X26;"), "&&&");
+        assert_eq!(decode_entities("&#233;t&#xE9;"), "été");
+        assert_eq!(decode_entities("&#x1F600;"), "😀");
+    }
+
+    #[test]
+    fn malformed_entities_are_left_alone() {
+        for text in ["&", "AT&T", "&amp", "&unknown;", "&#;", "&#x;", "&#xZZ;", "&#xD800;", "&#99999999;", "&#x110000;", "&averyverylongname;"] {
+            assert!(matches!(decode_entities(text), Cow::Borrowed(_)), "{}", text);
+        }
+        assert_eq!(decode_entities("&&amp;;"), "&&;");
+        assert_eq!(decode_entities("a & b &amp c &amp;"), "a & b &amp c &");
+    }
+}
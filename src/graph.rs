@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use rusqlite::{Connection, OptionalExtension};
+use crate::adjacency;
+use crate::meta::has_link_lists;
+
+/// Page ids as stored in the `pages` table
+pub type PageId = u32;
+
+/// Read access to a link graph, whatever it is stored in. Titles are normalized.
+pub trait LinkGraph {
+    /// One more than the largest page id, for sizing per-page arrays
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn id(&self, title: &str) -> Option<PageId>;
+
+    fn title(&self, id: PageId) -> String;
+
+    fn is_redirect(&self, id: PageId) -> bool;
+
+    /// The pages `id` links to, in page order. A redirect links only to its target.
+    fn links(&self, id: PageId) -> impl Iterator<Item = PageId> + '_;
+
+    /// The pages that link to `id`, including redirects to it, in id order
+    fn backlinks(&self, id: PageId) -> impl Iterator<Item = PageId> + '_;
+
+    /// The redirects that point directly at `id`
+    fn redirects_to(&self, id: PageId) -> Vec<PageId> {
+        self.backlinks(id).filter(|src| self.is_redirect(*src)).collect()
+    }
+
+    fn redirect_target(&self, id: PageId) -> Option<PageId> {
+        if !self.is_redirect(id) {
+            return None;
+        }
+        self.links(id).next()
+    }
+
+    /// The page at the end of the redirect chain starting at `id`, or `None` if `id` isn't a
+    /// redirect or the chain is broken or loops. Backends that store the result of
+    /// [`crate::schema::resolve_redirects`] look it up instead of following the chain.
+    fn resolved_target(&self, id: PageId) -> Option<PageId> {
+        let mut chain = vec![id];
+        let mut current = self.redirect_target(id)?;
+        while self.is_redirect(current) {
+            if chain.contains(&current) {
+                return None;
+            }
+            chain.push(current);
+            current = self.redirect_target(current)?;
+        }
+        Some(current)
+    }
+}
+
+/// The `pages` and `links` tables of a database, queried as the search needs them. Links packed
+/// into `link_lists` by `compact_links` are decoded on the fly.
+pub struct SqliteGraph {
+    conn: Connection,
+    len: usize,
+    compact: bool,
+}
+
+impl SqliteGraph {
+    pub fn new(conn: Connection) -> rusqlite::Result<SqliteGraph> {
+        let len = conn.query_row("SELECT coalesce(max(id) + 1, 0) FROM pages", (), |row| row.get(0))?;
+        let compact = has_link_lists(&conn);
+        Ok(SqliteGraph { conn, len, compact })
+    }
+
+    pub fn conn(&self) -> &Connection {
+        &self.conn
+    }
+
+    fn link_list(&self, query: &str, id: PageId) -> Vec<PageId> {
+        self.conn.prepare_cached(query).unwrap()
+            .query_row((id,), |row| row.get::<_, Option<Vec<u8>>>(0))
+            .optional().unwrap()
+            .flatten()
+            .map(|encoded| adjacency::decode(&encoded))
+            .unwrap_or_default()
+    }
+}
+
+impl LinkGraph for SqliteGraph {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn id(&self, title: &str) -> Option<PageId> {
+        self.conn.prepare_cached("SELECT id FROM pages WHERE title = ?").unwrap()
+            .query_row((title,), |row| row.get(0))
+            .optional().unwrap()
+    }
+
+    fn title(&self, id: PageId) -> String {
+        self.conn.prepare_cached("SELECT title FROM pages WHERE id = ?").unwrap()
+            .query_row((id,), |row| row.get(0))
+            .unwrap()
+    }
+
+    fn is_redirect(&self, id: PageId) -> bool {
+        self.conn.prepare_cached("SELECT is_redirect FROM pages WHERE id = ?").unwrap()
+            .query_row((id,), |row| row.get(0))
+            .unwrap()
+    }
+
+    fn resolved_target(&self, id: PageId) -> Option<PageId> {
+        self.conn.prepare_cached("SELECT resolved_target FROM pages WHERE id = ?").unwrap()
+            .query_row((id,), |row| row.get(0))
+            .unwrap()
+    }
+
+    // Rows can't be borrowed past the statement that reads them, so these still collect
+    fn links(&self, id: PageId) -> impl Iterator<Item = PageId> + '_ {
+        let links: Vec<PageId> = if self.compact {
+            self.link_list("SELECT links FROM link_lists WHERE id = ?", id)
+        } else {
+            self.conn.prepare_cached("SELECT dst_id FROM links WHERE src_id = ? ORDER BY ordinal").unwrap()
+                .query_map((id,), |row| row.get(0)).unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        links.into_iter()
+    }
+
+    fn backlinks(&self, id: PageId) -> impl Iterator<Item = PageId> + '_ {
+        let backlinks: Vec<PageId> = if self.compact {
+            self.link_list("SELECT backlinks FROM link_lists WHERE id = ?", id)
+        } else {
+            self.conn.prepare_cached("SELECT src_id FROM backlinks WHERE dst_id = ? ORDER BY src_id").unwrap()
+                .query_map((id,), |row| row.get(0)).unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap()
+        };
+        backlinks.into_iter()
+    }
+}
+
+/// A graph held in memory, built page by page or copied from another graph
+#[derive(Default)]
+pub struct MemoryGraph {
+    titles: Vec<String>,
+    redirects: Vec<bool>,
+    ids: HashMap<String, PageId>,
+    links: HashMap<PageId, Vec<PageId>>,
+    backlinks: HashMap<PageId, Vec<PageId>>,
+}
+
+impl MemoryGraph {
+    pub fn new() -> MemoryGraph {
+        MemoryGraph::default()
+    }
+
+    /// Adds a page, or returns the id of the page that already has this title
+    pub fn add_page(&mut self, title: &str, is_redirect: bool) -> PageId {
+        if let Some(id) = self.ids.get(title) {
+            return *id;
+        }
+
+        let id = self.titles.len() as PageId;
+        self.titles.push(title.to_string());
+        self.redirects.push(is_redirect);
+        self.ids.insert(title.to_string(), id);
+        id
+    }
+
+    pub fn add_link(&mut self, src: PageId, dst: PageId) {
+        self.links.entry(src).or_default().push(dst);
+        let backlinks = self.backlinks.entry(dst).or_default();
+        let position = backlinks.partition_point(|id| *id < src);
+        backlinks.insert(position, src);
+    }
+
+    /// Copies every page and link of `graph`, keeping its ids
+    pub fn load(graph: &impl LinkGraph) -> MemoryGraph {
+        let mut memory = MemoryGraph::new();
+        for id in 0..graph.len() as PageId {
+            let title = graph.title(id);
+            memory.ids.insert(title.clone(), id);
+            memory.titles.push(title);
+            memory.redirects.push(graph.is_redirect(id));
+
+            let links: Vec<PageId> = graph.links(id).collect();
+            for link in &links {
+                memory.backlinks.entry(*link).or_default().push(id);
+            }
+            if !links.is_empty() {
+                memory.links.insert(id, links);
+            }
+        }
+        memory
+    }
+}
+
+impl LinkGraph for MemoryGraph {
+    fn len(&self) -> usize {
+        self.titles.len()
+    }
+
+    fn id(&self, title: &str) -> Option<PageId> {
+        self.ids.get(title).copied()
+    }
+
+    fn title(&self, id: PageId) -> String {
+        self.titles[id as usize].clone()
+    }
+
+    fn is_redirect(&self, id: PageId) -> bool {
+        self.redirects[id as usize]
+    }
+
+    fn links(&self, id: PageId) -> impl Iterator<Item = PageId> + '_ {
+        self.links.get(&id).into_iter().flatten().copied()
+    }
+
+    fn backlinks(&self, id: PageId) -> impl Iterator<Item = PageId> + '_ {
+        self.backlinks.get(&id).into_iter().flatten().copied()
+    }
+}
@@ -0,0 +1,11 @@
+pub mod adjacency;
+pub mod csr;
+pub mod entities;
+pub mod graph;
+pub mod meta;
+pub mod multistream;
+pub mod namespaces;
+pub mod schema;
+pub mod search;
+pub mod title;
+pub mod wikitext;
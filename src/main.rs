@@ -1,64 +1,232 @@
-use std::collections::{HashSet, VecDeque};
-use std::collections::hash_map::DefaultHasher;
-use std::env;
+use std::{env, fs, process};
 use std::hash::{Hash, Hasher};
 use std::io::Write;
 use std::ops::Deref;
 use std::rc::Rc;
+use std::str::FromStr;
 use std::time::Instant;
 use hhmmss::Hhmmss;
 use num_format::{Locale, ToFormattedString};
-use rusqlite::{Connection, Error};
+use wiki_3::csr::CsrGraph;
+use wiki_3::graph::{LinkGraph, MemoryGraph, PageId, SqliteGraph};
+use wiki_3::meta::{self, has_column};
+use regex::Regex;
+use wiki_3::search::{all_shortest_paths, bfs, bidirectional_bfs, constrained_search, disjoint_paths, k_shortest_paths, path_length, print_progress, weighted_search, AlternativePaths, Constraints, Metric, ShortestPaths, Step, Weights};
+use wiki_3::namespaces::Namespaces;
+use wiki_3::title::normalize;
 
 
+const USAGE: &str = "Usage: wiki-3 [<start> <target>] [--csr <path>] [--memory] [--bidirectional] \
+    [--all-paths] [--max-paths <n>] [--dag] [--k-shortest <k>] [--disjoint <k>] \
+    [--weighted hops|avoid-hubs|prefer-hubs] [--astar] [--redirect-cost <cost>] [--counts <db>] \
+    [--exclude <title>]... [--exclude-pattern <regex>]... [--no-redirects] [--max-hops <n>] [--via <title>]...";
+
 // No Rc: 10.1M Cache - 4.3GB
 // Rc: 15M Cache - 8.2GB
 // Double Rc:  10.6M - 1.2GB
 
-fn to_titlecase(name: &String) -> String {
-    let mut new_name = String::with_capacity(name.len());
+/// Which search to run between the two pages
+enum Mode {
+    Shortest,
+    Bidirectional,
+    /// Every shortest path, listing up to `max_paths` of them or drawing them as a DAG
+    AllShortest { max_paths: usize, dag: bool },
+    /// The k shortest loopless paths
+    KShortest(usize),
+    /// Up to k paths sharing no pages but the ends
+    Disjoint(usize),
+    /// A shortest path following the rules given on the command line
+    Constrained(Rules),
+    /// The cheapest path under a metric, with reference counts from `counts_path`
+    Weighted { metric: Metric, astar: bool, redirect_cost: f64, counts_path: Option<String> },
+}
 
-    let mut capitalise = false;
-    for c in name.chars() {
-        if c == ' ' {
-            capitalise = true;
-            new_name.push(' ');
-        }
-        else if capitalise {
-            new_name.push(c.to_uppercase().next().unwrap());
-            capitalise = false;
-        }
-        else {
-            new_name.push(c);
-            capitalise = false;
+impl Mode {
+    fn name(&self) -> &'static str {
+        match self {
+            Mode::Shortest => "shortest path",
+            Mode::Bidirectional => "bidirectional",
+            Mode::AllShortest { .. } => "all shortest paths",
+            Mode::KShortest(_) => "k-shortest paths",
+            Mode::Disjoint(_) => "disjoint paths",
+            Mode::Constrained(_) => "constrained",
+            Mode::Weighted { .. } => "weighted",
         }
     }
+}
+
+/// Path rules as given on the command line, before the titles are looked up
+#[derive(Default)]
+struct Rules {
+    excluded: Vec<String>,
+    excluded_patterns: Vec<Regex>,
+    no_redirects: bool,
+    max_hops: Option<u32>,
+    waypoints: Vec<String>,
+}
+
+/// Prints how to run this and exits, for arguments it can't make sense of
+fn usage() -> ! {
+    println!("{}", USAGE);
+    process::exit(2);
+}
 
-    new_name
+/// Parses the argument after `flag`
+fn value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> T {
+    let Some(value) = args.next() else {
+        println!("{} needs a value", flag);
+        usage()
+    };
+    value.parse().unwrap_or_else(|_| {
+        println!("'{}' isn't a valid value for {}", value, flag);
+        usage()
+    })
 }
 
 fn main() {
-    let mut args: Vec<String> = env::args().into_iter().collect();
+    let mut csr_path: Option<String> = None;
+    let mut in_memory = false;
+    let mut bidirectional = false;
+    let mut all_paths = false;
+    let mut dag = false;
+    let mut max_paths = None;
+    let mut k_shortest = None;
+    let mut disjoint = None;
+    let mut metric = None;
+    let mut astar = false;
+    let mut redirect_cost = None;
+    let mut counts_path: Option<String> = None;
+    let mut rules = Rules::default();
+    let mut titles = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--csr" => csr_path = Some(value(&mut args, &arg)),
+            "--memory" => in_memory = true,
+            "--bidirectional" => bidirectional = true,
+            "--all-paths" => all_paths = true,
+            "--dag" => dag = true,
+            "--max-paths" => max_paths = Some(value(&mut args, &arg)),
+            "--k-shortest" => {
+                let k: usize = value(&mut args, &arg);
+                if k == 0 {
+                    println!("--k-shortest needs at least 1 path");
+                    usage()
+                }
+                k_shortest = Some(k);
+            }
+            "--disjoint" => {
+                let k: usize = value(&mut args, &arg);
+                if k == 0 {
+                    println!("--disjoint needs at least 1 path");
+                    usage()
+                }
+                disjoint = Some(k);
+            }
+            "--weighted" => {
+                let name: String = value(&mut args, &arg);
+                let Some(parsed) = Metric::parse(&name) else {
+                    println!("Unknown metric '{}' - use hops, avoid-hubs or prefer-hubs", name);
+                    usage()
+                };
+                metric = Some(parsed);
+            }
+            "--astar" => astar = true,
+            "--redirect-cost" => {
+                let cost: f64 = value(&mut args, &arg);
+                // Dijkstra needs costs of at least 0, and infinite ones make every total the same
+                if !cost.is_finite() || cost < 0.0 {
+                    println!("--redirect-cost has to be a number of at least 0, not '{}'", cost);
+                    usage()
+                }
+                redirect_cost = Some(cost);
+            }
+            "--counts" => counts_path = Some(value(&mut args, &arg)),
+            "--exclude" => rules.excluded.push(value(&mut args, &arg)),
+            "--exclude-pattern" => rules.excluded_patterns.push(value(&mut args, &arg)),
+            "--no-redirects" => rules.no_redirects = true,
+            "--max-hops" => rules.max_hops = Some(value(&mut args, &arg)),
+            "--via" => rules.waypoints.push(value(&mut args, &arg)),
+            _ if arg.starts_with("--") => {
+                println!("Unknown option '{}'", arg);
+                usage()
+            }
+            _ if titles.len() < 2 => titles.push(arg),
+            _ => {
+                println!("Unexpected argument '{}' - give at most two titles", arg);
+                usage()
+            }
+        }
+    }
+    let weighted = metric.is_some() || astar || redirect_cost.is_some() || counts_path.is_some();
+    let constrained = !rules.excluded.is_empty() || !rules.excluded_patterns.is_empty() || rules.no_redirects
+        || rules.max_hops.is_some() || !rules.waypoints.is_empty();
 
-    // ! CASE SENSITIVE
-    // let starting_at = "Tobi 12";
-    // let searching_for = "xxINVALIDxx";
+    if constrained && (k_shortest.is_some() || disjoint.is_some() || all_paths || dag || max_paths.is_some() || bidirectional || weighted) {
+        println!("--exclude, --exclude-pattern, --no-redirects, --max-hops and --via only work with the default search");
+        usage()
+    }
+    if weighted && (k_shortest.is_some() || disjoint.is_some() || all_paths || dag || max_paths.is_some() || bidirectional) {
+        println!("--weighted, --astar, --redirect-cost and --counts can't be combined with other searches");
+        usage()
+    }
 
-    let (mut starting_at, mut searching_for) = if args.len() >= 3 {
-        let b = args.remove(2);
-        let a = args.remove(1);
-        (a, b)
+    let mode = if weighted {
+        Mode::Weighted { metric: metric.unwrap_or(Metric::Hops), astar, redirect_cost: redirect_cost.unwrap_or(0.0), counts_path }
+    }
+    else if constrained {
+        Mode::Constrained(rules)
+    }
+    else if let Some(k) = k_shortest {
+        Mode::KShortest(k)
+    }
+    else if let Some(k) = disjoint {
+        Mode::Disjoint(k)
+    }
+    else if all_paths || dag || max_paths.is_some() {
+        Mode::AllShortest { max_paths: max_paths.unwrap_or(100), dag }
+    }
+    else if bidirectional {
+        Mode::Bidirectional
     }
     else {
-        ("Bedford".to_string(), "Paul Singer (businessman)".to_string())
+        Mode::Shortest
     };
 
-    drop(args);
+    // ! CASE SENSITIVE
+    // let starting_at = "Tobi 12";
+    // let searching_for = "xxINVALIDxx";
 
+    let (starting_at, searching_for) = match titles.len() {
+        0 => ("Bedford".to_string(), "Paul Singer (businessman)".to_string()),
+        2 => {
+            let b = titles.pop().unwrap();
+            let a = titles.pop().unwrap();
+            (a, b)
+        }
+        _ => {
+            println!("Give both the title to start at and the title to search for");
+            usage()
+        }
+    };
 
     let start_time = Instant::now();
 
-    let db = Connection::open("completed-table.db").unwrap();
+    if let Some(csr_path) = csr_path {
+        let graph = CsrGraph::open(&csr_path).unwrap();
+        // The graph file has no namespaces, so they come from the database it was exported from
+        let namespaces = if fs::exists("completed-table.db").unwrap() {
+            Namespaces::load(&meta::open("completed-table.db").unwrap())
+        } else {
+            Namespaces::enwiki()
+        };
+
+        search(&graph, &namespaces, starting_at, searching_for, mode, start_time);
+        return;
+    }
+
+    let db = meta::open("completed-table.db").unwrap();
     db.execute_batch(
         "PRAGMA synchronous = 0;
               PRAGMA locking_mode = EXCLUSIVE;
@@ -67,178 +235,266 @@ fn main() {
               "
         ,
     ).unwrap();
-    let mut cached_query = db.prepare_cached("SELECT * FROM page_references WHERE title = ?").unwrap();
+    let namespaces = Namespaces::load(&db);
+    let graph = SqliteGraph::new(db).unwrap();
+
+    if in_memory {
+        println!("Loading {} pages into memory", graph.len().to_formatted_string(&Locale::en));
+        let graph = MemoryGraph::load(&graph);
+        println!("Loaded in {}", start_time.elapsed().hhmmssxxx());
+        search(&graph, &namespaces, starting_at, searching_for, mode, start_time);
+    }
+    else {
+        search(&graph, &namespaces, starting_at, searching_for, mode, start_time);
+    }
+}
 
-    for p in [&mut starting_at, &mut searching_for] {
-        loop {
-            let res: Result<(String, bool), Error> = cached_query.query_row(
-                (p.to_owned(),),
-                |row| Ok((row.get(1).unwrap(), row.get(2).unwrap()))
-            );
+fn search(graph: &impl LinkGraph, namespaces: &Namespaces, mut starting_at: String, mut searching_for: String, mode: Mode, start_time: Instant) {
+    let Some([starting_id, searching_for_id]) = choose_pages([&mut starting_at, &mut searching_for], graph, namespaces) else {
+        return;
+    };
 
-            if let Ok(row) = res{
-                if row.1 {
-                    println!("'{p}' is a valid redirect to '{}'", row.0);
+    println!("Finding '{}' -> '{}'", starting_at, searching_for);
+    let Some(starting_id) = starting_id else {
+        println!("'{}' has no page to start from", starting_at);
+        return;
+    };
 
-                    print!("Would you like to use the page this redirect points to? (Y/N): ");
-                    std::io::stdout().flush().ok();
-                    let mut r = String::new();
-                    std::io::stdin().read_line(&mut r).unwrap();
+    // A search for a page that doesn't exist goes through everything reachable instead, which
+    // only means something for a plain search
+    let searching_for_id = match (searching_for_id, &mode) {
+        (Some(id), _) => Some(id),
+        (None, Mode::Shortest) => {
+            println!("'{}' has no page, so searching every page reachable from '{}'", searching_for, starting_at);
+            None
+        }
+        (None, mode) => {
+            println!("'{}' has no page to search for - the {} search needs one", searching_for, mode.name());
+            return;
+        }
+    };
 
-                    if r.chars().next().unwrap().to_uppercase().next().unwrap() == 'Y' {
-                        *p = row.0;
-                        continue;
-                    }
-                }
-                else {
-                    println!("'{p}' is a valid page");
-                }
+    let result = match (mode, searching_for_id) {
+        (Mode::AllShortest { max_paths, dag }, Some(searching_for_id)) => {
+            let paths = all_shortest_paths(graph, starting_id, searching_for_id);
+            print_shortest_paths(graph, &paths, max_paths, dag);
+            println!("Completed in {}", start_time.elapsed().hhmmssxxx());
+            print_progress(paths.searched, start_time, paths.visited, 0);
+            return;
+        }
+        (Mode::KShortest(k), Some(searching_for_id)) => {
+            print_alternative_paths(graph, &k_shortest_paths(graph, starting_id, searching_for_id, k), start_time);
+            return;
+        }
+        (Mode::Disjoint(k), Some(searching_for_id)) => {
+            print_alternative_paths(graph, &disjoint_paths(graph, starting_id, searching_for_id, k), start_time);
+            return;
+        }
+        (Mode::Constrained(rules), Some(searching_for_id)) => {
+            let Some(constraints) = look_up_rules(graph, namespaces, rules) else {
+                return;
+            };
+            println!("Constraints:");
+            for line in constraints.describe(graph) {
+                println!("  {}", line);
             }
-            else {
-                println!("'{p}' is invalid");
-
-                print!("Would you like to try title case? (Y/N): ");
-                std::io::stdout().flush().ok();
-                let mut r = String::new();
-                std::io::stdin().read_line(&mut r).unwrap();
-
-                if r.chars().next().unwrap().to_uppercase().next().unwrap() == 'Y' {
-                    *p = to_titlecase(&p);
-                    continue;
+            let result = constrained_search(graph, starting_id, searching_for_id, &constraints);
+            match &result.path {
+                Some(path) => {
+                    println!("{} links:", path_length(path));
+                    println!("{}", PageHolder::from_path(graph, path).to_str());
                 }
-
-                print!("Would you like to continue anyway? (Y/N): ");
-                std::io::stdout().flush().ok();
-                let mut r = String::new();
-                std::io::stdin().read_line(&mut r).unwrap();
-
-                if r.chars().next().unwrap().to_uppercase().next().unwrap() != 'Y' {
+                None => println!("No path follows the constraints")
+            }
+            println!("Completed in {}", start_time.elapsed().hhmmssxxx());
+            print_progress(result.searched, start_time, result.visited, result.open_set);
+            return;
+        }
+        (Mode::Weighted { metric, astar, redirect_cost, counts_path }, Some(searching_for_id)) => {
+            let reference_counts = if metric == Metric::Hops {
+                Vec::new()
+            } else {
+                let Some(reference_counts) = load_reference_counts(counts_path) else {
                     return;
+                };
+                reference_counts
+            };
+            let weights = Weights::new(metric, reference_counts, redirect_cost);
+            let (result, cost) = weighted_search(graph, starting_id, searching_for_id, &weights, astar);
+
+            println!(
+                "Optimizing {} with {} (redirects cost {})",
+                metric.name(), if astar { "A*" } else { "best-first search" }, redirect_cost
+            );
+            match &result.path {
+                Some(path) => {
+                    println!("Total cost {:.3}, {} links:", cost, path_length(path));
+                    println!("{}", PageHolder::from_path(graph, path).to_str());
                 }
+                None => println!("No more pages!")
             }
-
-            break;
+            println!("Completed in {}", start_time.elapsed().hhmmssxxx());
+            print_progress(result.searched, start_time, result.visited, result.open_set);
+            return;
         }
+        (Mode::Bidirectional, Some(searching_for_id)) => bidirectional_bfs(graph, starting_id, searching_for_id),
+        (Mode::Shortest, searching_for_id) => bfs(graph, starting_id, searching_for_id),
+        (_, None) => unreachable!("only the plain search runs without a target")
+    };
+    match &result.path {
+        Some(path) => println!("{}", PageHolder::from_path(graph, path).to_str()),
+        None => println!("No more pages!")
     }
 
-    println!("Finding '{}' -> '{}'", starting_at, searching_for);
-
-
-    let starting_page = PageHolder::from_page(Page::new(
-        starting_at,
-        false,
-        None,
-    ));
-
-    let mut visited = HashSet::with_capacity(17_000_000);
-    visited.insert(starting_page.clone());
-
-    //? Consider linked list
-    let mut open_set = VecDeque::with_capacity(1_000_000);
-    open_set.push_back(starting_page);
-
-    let mut count: u32 = 0;
+    println!("Completed in {}", start_time.elapsed().hhmmssxxx());
+    print_progress(result.searched, start_time, result.visited, result.open_set);
+}
 
-    'main_loop: loop {
-        let mut page = open_set.pop_front().unwrap();
-        if open_set.is_empty() && visited.len() != 1 {
-            println!("Last page: {}", page.to_str());
+fn print_shortest_paths(graph: &impl LinkGraph, paths: &ShortestPaths, max_paths: usize, dag: bool) {
+    let Some(length) = paths.length else {
+        println!("No more pages!");
+        return;
+    };
+    let total = paths.total();
+    println!("{} shortest paths of {} links", total.to_formatted_string(&Locale::en), length);
+
+    if dag {
+        for (depth, layer) in paths.layers.iter().enumerate() {
+            println!("Layer {}:", depth);
+            for id in layer {
+                let redirect = if graph.is_redirect(*id) { " [redirect]" } else { "" };
+                let parents: Vec<String> = paths.parents.get(id).into_iter().flatten().map(|parent| graph.title(*parent)).collect();
+                let parents = if parents.is_empty() { String::new() } else { format!(" <- {}", parents.join(", ")) };
+                println!(
+                    "  {}{} ({} paths){}",
+                    graph.title(*id), redirect, paths.paths_through(*id).to_formatted_string(&Locale::en), parents
+                );
+            }
         }
-
-        count += 1;
-        if count % 10_000 == 0 {
-            println!(
-                "Pages searched: {} [{:?}/page] | Cache size: {} | Open set size: {}",
-                count.to_formatted_string(&Locale::en),
-                start_time.elapsed() / count,
-                visited.len().to_formatted_string(&Locale::en),
-                open_set.len().to_formatted_string(&Locale::en),
-            );
+    }
+    else {
+        let listed = paths.paths(max_paths);
+        for (i, path) in listed.iter().enumerate() {
+            println!("\nPath {}:", i + 1);
+            println!("{}", PageHolder::from_path(graph, path).to_str());
+        }
+        if (listed.len() as u128) < total {
+            println!("\nListed {} of {} paths - raise --max-paths to see more", listed.len(), total.to_formatted_string(&Locale::en));
         }
+    }
 
-        let mut loop_count: usize = 0;
-        let links: String;
-        loop {
-            if loop_count >= 20 {
-                println!("Link following for '{}' has reached a depth of {}", page.get_page().page, loop_count);
-                continue 'main_loop;
-            }
-            loop_count += 1;
+    let unavoidable: Vec<String> = paths.unavoidable().into_iter().map(|id| graph.title(id)).collect();
+    println!("Every path goes through: {}", unavoidable.join(" -> "));
+}
 
-            if page.get_page().page.as_str() == searching_for.as_str() {
-                println!("{}", page.to_str());
-                break 'main_loop;
-            }
+fn print_alternative_paths(graph: &impl LinkGraph, paths: &AlternativePaths, start_time: Instant) {
+    if paths.paths.is_empty() {
+        println!("No more pages!");
+    }
+    for (i, path) in paths.paths.iter().enumerate() {
+        println!("\nPath {} ({} links):", i + 1, path_length(path));
+        println!("{}", PageHolder::from_path(graph, path).to_str());
+    }
 
-            let mut res = cached_query.query_row(
-                (&page.get_page().page,),
-                |row| Ok((row.get(1).unwrap(), row.get(2).unwrap()))
-            );
+    println!("Completed in {}", start_time.elapsed().hhmmssxxx());
+    print_progress(paths.searched, start_time, paths.visited, 0);
+}
 
-            if res.is_err() {
-                let modified_name = to_titlecase(&page.get_page().page);
+/// Reads `reference_count_with_redirects` by page id from `path`, or from whichever of
+/// `completed-table.db` and `reference-count-table.db` has it
+fn load_reference_counts(path: Option<String>) -> Option<Vec<u32>> {
+    let candidates = match path {
+        Some(path) => vec![path],
+        None => vec!["completed-table.db".to_string(), "reference-count-table.db".to_string()]
+    };
+    let Some(conn) = candidates.iter()
+        .filter(|path| fs::exists(path).unwrap())
+        .map(|path| meta::open(path).unwrap())
+        .find(|conn| has_column(conn, "pages", "reference_count_with_redirects"))
+    else {
+        println!("No reference counts in {} - run count_references first", candidates.join(" or "));
+        return None;
+    };
 
-                res = db.query_row(
-                    "SELECT * FROM page_references WHERE title = ?",
-                    (&modified_name,),
-                    |row| Ok((row.get(1).unwrap(), row.get(2).unwrap()))
-                );
-            }
+    let pages: usize = conn.query_row("SELECT coalesce(max(id) + 1, 0) FROM pages", (), |row| row.get(0)).unwrap();
+    let mut counts = vec![0; pages];
+    let mut statement = conn.prepare("SELECT id, reference_count_with_redirects FROM pages").unwrap();
+    let mut rows = statement.query(()).unwrap();
+    while let Some(row) = rows.next().unwrap() {
+        let id: usize = row.get(0).unwrap();
+        counts[id] = row.get(1).unwrap();
+    }
+    Some(counts)
+}
 
-            if let Err(_e) = res {
-                // println!("Fetching {:?}->{} failed with: {:?}", page.from.last(), page.page, e);
-                continue 'main_loop;
+/// Finds the pages named in `rules`, or `None` if one doesn't exist
+fn look_up_rules(graph: &impl LinkGraph, namespaces: &Namespaces, rules: Rules) -> Option<Constraints> {
+    let look_up = |titles: Vec<String>| -> Option<Vec<PageId>> {
+        titles.into_iter().map(|title| {
+            let title = normalize(&title, namespaces);
+            let id = graph.id(&title);
+            if id.is_none() {
+                println!("'{}' is invalid", title);
             }
+            id
+        }).collect()
+    };
 
-            let (links_, redirect): (String, bool) = res.unwrap();
+    Some(Constraints {
+        excluded: look_up(rules.excluded)?.into_iter().collect(),
+        excluded_patterns: rules.excluded_patterns,
+        no_redirects: rules.no_redirects,
+        max_hops: rules.max_hops,
+        waypoints: look_up(rules.waypoints)?,
+    })
+}
 
-            if redirect {
-                if links_.is_empty() {
-                    continue 'main_loop;
-                }
-                page = PageHolder::add_to_path(&page, links_.clone(), true);
+/// Normalizes and checks the start and end titles with [`normalize`], offering to follow
+/// redirects. Returns the id of each title that exists, or `None` if the user gives up.
+fn choose_pages(titles: [&mut String; 2], graph: &impl LinkGraph, namespaces: &Namespaces) -> Option<[Option<PageId>; 2]> {
+    let mut ids = [None, None];
+    for (p, id) in titles.into_iter().zip(&mut ids) {
+        loop {
+            *p = normalize(p, namespaces);
 
-                visited.insert(page.clone());
-                continue;
-            }
+            *id = graph.id(p);
+            if let Some(page_id) = *id {
+                if graph.is_redirect(page_id) {
+                    let target = graph.resolved_target(page_id).map(|target| graph.title(target)).unwrap_or_default();
+                    println!("'{p}' is a valid redirect to '{}'", target);
 
-            links = links_;
-            break;
-        }
+                    print!("Would you like to use the page this redirect points to? (Y/N): ");
+                    std::io::stdout().flush().ok();
+                    let mut r = String::new();
+                    std::io::stdin().read_line(&mut r).unwrap();
 
-        for link in links.split("<|>") {
-            if link.is_empty() {
-                continue;
+                    if r.chars().next().unwrap().to_uppercase().next().unwrap() == 'Y' {
+                        *p = target;
+                        continue;
+                    }
+                }
+                else {
+                    println!("'{p}' is a valid page");
+                }
             }
+            else {
+                println!("'{p}' is invalid");
 
-            let new_page = PageHolder::add_to_path(&page, link.to_string(), false);
-
-            if !visited.insert(new_page.clone()) {
-                continue;
-            }
+                print!("Would you like to continue anyway? (Y/N): ");
+                std::io::stdout().flush().ok();
+                let mut r = String::new();
+                std::io::stdin().read_line(&mut r).unwrap();
 
-            if link == searching_for.as_str() {
-                println!("{}", new_page.to_str());
-                break 'main_loop;
+                if r.chars().next().unwrap().to_uppercase().next().unwrap() != 'Y' {
+                    return None;
+                }
             }
 
-            open_set.push_back(new_page);
-        }
-
-        if open_set.is_empty() {
-            println!("No more pages!");
             break;
         }
     }
 
-    println!("Completed in {}", start_time.elapsed().hhmmssxxx());
-    println!(
-        "Pages searched: {} [{:?}/page] | Cache size: {} | Open set size: {}",
-        count.to_formatted_string(&Locale::en),
-        start_time.elapsed() / count,
-        visited.len().to_formatted_string(&Locale::en),
-        open_set.len().to_formatted_string(&Locale::en),
-    );
+    Some(ids)
 }
 
 #[derive(Clone)]
@@ -287,50 +543,55 @@ impl PageHolder {
         print_string
     }
 
-    pub fn add_to_path(prev: &PageHolder, next: String, from_redirect: bool) -> PageHolder {
+    pub fn add_to_path(prev: &PageHolder, id: PageId, next: String, from_redirect: bool) -> PageHolder {
         PageHolder::from_page(
             Page::new(
+                id,
                 next,
                 from_redirect,
                 Some(prev.clone())
             )
         )
     }
+
+    pub fn from_path(graph: &impl LinkGraph, path: &[Step]) -> PageHolder {
+        let mut page = PageHolder::from_page(Page::new(path[0].id, graph.title(path[0].id), false, None));
+        for step in &path[1..] {
+            page = PageHolder::add_to_path(&page, step.id, graph.title(step.id), step.from_redirect);
+        }
+        page
+    }
 }
 
 impl Hash for PageHolder {
     fn hash<H: Hasher>(&self, state: &mut H) {
 
-        state.write_u64(self.get_page().hash);
+        state.write_u32(self.get_page().id);
     }
 }
 
 impl PartialEq for PageHolder {
     fn eq(&self, other: &Self) -> bool {
-        self.get_page().hash == other.get_page().hash
+        self.get_page().id == other.get_page().id
     }
 }
 
 impl Eq for PageHolder {}
 
 struct Page {
+    pub id: PageId,
     pub page: String,
     pub from_redirect: bool,
-    pub hash: u64,
     pub from: Option<PageHolder>,
 }
 
 impl Page {
-    pub fn new(page: String, from_redirect: bool, from: Option<PageHolder>) -> Page {
-        let mut hasher = DefaultHasher::new();
-        page.hash(&mut hasher);
-        let hash = hasher.finish();
-
+    pub fn new(id: PageId, page: String, from_redirect: bool, from: Option<PageHolder>) -> Page {
         Page {
+            id,
             page,
             from_redirect,
-            hash,
             from
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,258 @@
+use std::error::Error;
+use rusqlite::Connection;
+use crate::schema::{build_backlinks, build_link_tables, resolve_redirects};
+
+/// Version of the tables [`build_link_tables`] creates, stored under `schema_version`:
+/// 1. `page_references` with every page's links as one `<|>`-separated string
+/// 2. `pages`, `links` and `unresolved_links` with integer ids
+/// 3. resolved redirect chains in `pages` and `redirect_reports`
+/// 4. `backlinks`
+/// 5. the `cause` of each failed insert in `page_reference_errors`
+pub const SCHEMA_VERSION: u32 = 5;
+
+/// `link_storage` of a database whose `links` and `backlinks` were packed into `link_lists` by
+/// `compact_links`, see [`crate::adjacency`]
+pub const COMPACT_STORAGE: &str = "compact";
+
+/// `link_storage` of a database packed by `compact_links --keep-rows`, which has both. Without
+/// a `link_storage`, a database only has the rows.
+pub const BOTH_STORAGE: &str = "rows and link_lists";
+
+/// The `parser` recorded by `process_data_slow`, whose `page_references` has one row per link
+/// and none of the id tables
+pub const SLOW_PARSER: &str = "process_data_slow";
+
+/// Records a fact about how the database was built in the `meta(key, value)` table
+pub fn set(conn: &Connection, key: &str, value: impl ToString) {
+    conn.execute("CREATE TABLE IF NOT EXISTS meta (key TEXT PRIMARY KEY, value TEXT)", ()).unwrap();
+    conn.execute("INSERT OR REPLACE INTO meta VALUES (?, ?)", (key, value.to_string())).unwrap();
+}
+
+pub fn get(conn: &Connection, key: &str) -> Option<String> {
+    conn.query_row("SELECT value FROM meta WHERE key = ?", [key], |row| row.get(0)).ok()
+}
+
+pub fn has_table(conn: &Connection, name: &str) -> bool {
+    conn.query_row("SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?", [name], |row| row.get::<_, u32>(0))
+        .unwrap() > 0
+}
+
+pub fn has_column(conn: &Connection, table: &str, column: &str) -> bool {
+    conn.query_row("SELECT count(*) FROM pragma_table_info(?) WHERE name = ?", [table, column], |row| row.get::<_, u32>(0))
+        .unwrap() > 0
+}
+
+/// The date in a dump file name like `enwiki-20231101-pages-articles-multistream.xml.bz2`
+pub fn dump_date(dump_path: &str) -> Option<String> {
+    let name = dump_path.rsplit(['/', '\\']).next()?;
+    let date = name.split('-').find(|part| part.len() == 8 && part.bytes().all(|b| b.is_ascii_digit()))?;
+    Some(format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]))
+}
+
+/// The schema version of a database, from `meta` or, for databases built before it was
+/// recorded, from the tables it has
+fn schema_version(conn: &Connection) -> Result<u32, Box<dyn Error>> {
+    if get(conn, "parser").as_deref() == Some(SLOW_PARSER) || has_column(conn, "page_references", "reference") {
+        return Err(format!("The database was built by {}, which stores one row per link. Rebuild it with process_data_no_xml.", SLOW_PARSER).into());
+    }
+    if let Some(version) = get(conn, "schema_version") {
+        return Ok(version.parse()?);
+    }
+
+    if has_column(conn, "page_reference_errors", "cause") && has_table(conn, "pages") {
+        Ok(5)
+    } else if has_table(conn, "backlinks") {
+        Ok(4)
+    } else if has_column(conn, "pages", "resolved_target") {
+        Ok(3)
+    } else if has_table(conn, "pages") {
+        Ok(2)
+    } else if has_column(conn, "page_references", "links") {
+        Ok(1)
+    } else {
+        Err("The database has no link tables".into())
+    }
+}
+
+/// Migrates a database built by an older `process_data_no_xml` to [`SCHEMA_VERSION`], or refuses
+/// one built by `process_data_slow` or a newer version
+pub fn check_schema(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    let mut version = schema_version(conn)?;
+    if version > SCHEMA_VERSION {
+        return Err(format!("The database has schema version {}, but this build only understands up to {}", version, SCHEMA_VERSION).into());
+    }
+
+    while version < SCHEMA_VERSION {
+        println!("Migrating the database from schema version {} (the current version is {})", version, SCHEMA_VERSION);
+        match version {
+            1 => {
+                if !has_column(conn, "page_references", "namespace") {
+                    conn.execute("ALTER TABLE page_references ADD COLUMN namespace INTEGER DEFAULT 0", ())?;
+                }
+                build_link_tables(conn)?;
+                version = 4;
+            }
+            2 => {
+                // Rolled back when dropped, if resolving fails
+                let transaction = conn.unchecked_transaction()?;
+                transaction.execute_batch(
+                    "ALTER TABLE pages ADD COLUMN resolved_target INTEGER;
+                     ALTER TABLE pages ADD COLUMN redirect_status TEXT;
+                     ALTER TABLE pages ADD COLUMN redirect_chain INTEGER;"
+                )?;
+                resolve_redirects(&transaction)?;
+                transaction.commit()?;
+                version = 3;
+            }
+            3 => {
+                let transaction = conn.unchecked_transaction()?;
+                build_backlinks(&transaction)?;
+                transaction.commit()?;
+                version = 4;
+            }
+            _ => {
+                // Causes weren't recorded, `check` works out which of these rows are duplicate titles
+                if has_table(conn, "page_reference_errors") && !has_column(conn, "page_reference_errors", "cause") {
+                    conn.execute("ALTER TABLE page_reference_errors ADD COLUMN cause TEXT", ())?;
+                }
+                version = 5;
+            }
+        }
+        set(conn, "schema_version", version);
+    }
+    if get(conn, "schema_version").is_none() {
+        set(conn, "schema_version", version);
+    }
+    Ok(())
+}
+
+/// Opens a database built by `process_data_no_xml`, migrating it if it's out of date.
+///
+/// The migration rewrites the file in place, whichever tool opened it, so even `wiki-3` or
+/// `what_links_here` change an old database the first time they read it. Each step prints what
+/// it's doing before it starts, and a failed step is rolled back.
+pub fn open(path: &str) -> Result<Connection, Box<dyn Error>> {
+    if !std::path::Path::new(path).exists() {
+        return Err(format!("'{}' doesn't exist", path).into());
+    }
+    let conn = Connection::open(path)?;
+    check_schema(&conn).map_err(|e| format!("Can't use '{}': {}", path, e))?;
+    Ok(conn)
+}
+
+/// Whether links should be read from `link_lists` rather than the `links` and `backlinks` rows
+pub fn has_link_lists(conn: &Connection) -> bool {
+    matches!(get(conn, "link_storage").as_deref(), Some(COMPACT_STORAGE | BOTH_STORAGE))
+}
+
+/// Whether the `links` and `backlinks` tables are there
+pub fn has_link_rows(conn: &Connection) -> bool {
+    get(conn, "link_storage").as_deref() != Some(COMPACT_STORAGE)
+}
+
+/// Like [`open`], for tools that query the `links` and `backlinks` tables themselves
+pub fn open_with_link_rows(path: &str) -> Result<Connection, Box<dyn Error>> {
+    let conn = open(path)?;
+    if !has_link_rows(&conn) {
+        return Err(format!("'{}' has its links packed into link_lists - run compact_links --expand first", path).into());
+    }
+    Ok(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGES: [(&str, &str, bool); 4] = [
+        ("Bedford", "Luton<|>Missing", false),
+        ("Luton", "Bedford", false),
+        ("Beds", "Bedford", true),
+        ("Bedfordshire", "Beds", true),
+    ];
+
+    /// A database as `process_data_no_xml` left it at schema `version`, before `meta` existed
+    fn database_at(version: u32) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE page_references (title TEXT PRIMARY KEY, links TEXT, is_redirect INTEGER)", ()).unwrap();
+        for (title, links, is_redirect) in PAGES {
+            conn.execute("INSERT INTO page_references VALUES (?, ?, ?)", (title, links, is_redirect)).unwrap();
+        }
+        conn.execute("CREATE TABLE page_reference_errors (title TEXT, links TEXT, is_redirect INTEGER)", ()).unwrap();
+        conn.execute("INSERT INTO page_reference_errors VALUES ('Luton', '', 0)", ()).unwrap();
+        if version == 5 {
+            conn.execute("ALTER TABLE page_reference_errors ADD COLUMN cause TEXT", ()).unwrap();
+        }
+        if version == 1 {
+            return conn;
+        }
+
+        conn.execute("ALTER TABLE page_references ADD COLUMN namespace INTEGER DEFAULT 0", ()).unwrap();
+        build_link_tables(&conn).unwrap();
+        if version < 4 {
+            conn.execute("DROP TABLE backlinks", ()).unwrap();
+        }
+        if version < 3 {
+            conn.execute_batch(
+                "DROP TABLE redirect_reports;
+                 ALTER TABLE pages DROP COLUMN resolved_target;
+                 ALTER TABLE pages DROP COLUMN redirect_status;
+                 ALTER TABLE pages DROP COLUMN redirect_chain;"
+            ).unwrap();
+        }
+        conn
+    }
+
+    fn strings(conn: &Connection, sql: &str) -> Vec<String> {
+        let mut statement = conn.prepare(sql).unwrap();
+        statement.query_map((), |row| row.get(0)).unwrap().map(|row| row.unwrap()).collect()
+    }
+
+    #[test]
+    fn versions_are_recognised_from_the_tables() {
+        for version in 1..=SCHEMA_VERSION {
+            assert_eq!(schema_version(&database_at(version)).unwrap(), version);
+        }
+    }
+
+    #[test]
+    fn old_databases_are_migrated_to_the_current_schema() {
+        for version in 1..=SCHEMA_VERSION {
+            let conn = database_at(version);
+            check_schema(&conn).unwrap();
+            assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION, "from version {}", version);
+            if version < SCHEMA_VERSION {
+                assert_eq!(get(&conn, "schema_version"), Some(SCHEMA_VERSION.to_string()));
+            }
+
+            assert_eq!(strings(&conn, "SELECT src_id || ' ' || dst_id FROM links ORDER BY src_id, ordinal"), ["0 1", "1 0", "2 0", "3 2"]);
+            assert_eq!(strings(&conn, "SELECT dst_id || ' ' || src_id FROM backlinks"), ["0 1", "0 2", "1 0", "2 3"]);
+            assert_eq!(strings(&conn, "SELECT title || ' ' || resolved_target || ' ' || redirect_chain FROM pages WHERE is_redirect ORDER BY id"), ["Beds 0 1", "Bedfordshire 0 2"]);
+            assert_eq!(strings(&conn, "SELECT target FROM unresolved_links"), ["Missing"]);
+            assert!(has_column(&conn, "page_reference_errors", "cause"));
+        }
+    }
+
+    #[test]
+    fn databases_that_cannot_be_migrated_are_refused() {
+        let conn = Connection::open_in_memory().unwrap();
+        assert!(check_schema(&conn).is_err());
+
+        conn.execute("CREATE TABLE page_references (title TEXT, reference TEXT)", ()).unwrap();
+        assert!(check_schema(&conn).is_err());
+
+        let conn = database_at(SCHEMA_VERSION);
+        set(&conn, "parser", SLOW_PARSER);
+        assert!(check_schema(&conn).is_err());
+
+        let conn = database_at(SCHEMA_VERSION);
+        set(&conn, "schema_version", SCHEMA_VERSION + 1);
+        assert!(check_schema(&conn).is_err());
+    }
+
+    #[test]
+    fn dump_dates_come_from_the_file_name() {
+        assert_eq!(dump_date("/data/enwiki-20231101-pages-articles-multistream.xml.bz2").as_deref(), Some("2023-11-01"));
+        assert_eq!(dump_date("C:\\dumps\\dewiki-20240301-pages-articles.xml.bz2").as_deref(), Some("2024-03-01"));
+        assert_eq!(dump_date("dump.xml.bz2"), None);
+    }
+}
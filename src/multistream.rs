@@ -0,0 +1,176 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Take};
+use bzip2::read::{BzDecoder, MultiBzDecoder};
+
+/// A single bzip2 stream inside a `pages-articles-multistream` dump. Every stream after the
+/// first holds up to 100 whole `<page>` elements, so it can be decompressed on its own.
+#[derive(Clone, Copy, Debug)]
+pub struct Block {
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Reads the `-index.txt.bz2` companion of a multistream dump and returns the byte offsets of
+/// every stream that contains pages, in file order.
+///
+/// Each index line has the form `offset:page_id:title`.
+pub fn read_index(index_path: &str) -> io::Result<Vec<u64>> {
+    let reader = BufReader::new(MultiBzDecoder::new(File::open(index_path)?));
+
+    let mut offsets: Vec<u64> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+
+        let offset = line.split(':').next().unwrap().parse::<u64>().map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidData, format!("Invalid index line '{}': {}", line, e))
+        )?;
+
+        if offsets.last() != Some(&offset) {
+            offsets.push(offset);
+        }
+    }
+
+    offsets.sort_unstable();
+    offsets.dedup();
+    Ok(offsets)
+}
+
+/// Derives the index file name from a dump file name, e.g.
+/// `enwiki-20231101-pages-articles-multistream.xml.bz2` ->
+/// `enwiki-20231101-pages-articles-multistream-index.txt.bz2`
+pub fn index_path_for(dump_path: &str) -> String {
+    match dump_path.strip_suffix(".xml.bz2") {
+        Some(stem) => format!("{stem}-index.txt.bz2"),
+        None => format!("{dump_path}-index.txt.bz2")
+    }
+}
+
+pub struct MultistreamDump {
+    file: File,
+    index_path: String,
+    header: Block,
+    blocks: Vec<Block>,
+}
+
+impl MultistreamDump {
+    pub fn open(dump_path: &str, index_path: &str) -> io::Result<Self> {
+        let offsets = read_index(index_path)?;
+        let file = File::open(dump_path)?;
+        let file_len = file.metadata()?.len();
+
+        if offsets.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Index '{}' is empty", index_path)));
+        }
+
+        let blocks = offsets.iter().enumerate().map(|(i, offset)| {
+            let end = offsets.get(i + 1).copied().unwrap_or(file_len);
+            Block { offset: *offset, len: end - offset }
+        }).collect();
+
+        Ok(Self {
+            file,
+            index_path: index_path.to_string(),
+            header: Block { offset: 0, len: offsets[0] },
+            blocks,
+        })
+    }
+
+    /// The stream holding `<mediawiki>` and `<siteinfo>`, before the first page
+    pub fn header(&self) -> Block {
+        self.header
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Returns the blocks holding at least one page whose title starts with `title_prefix`,
+    /// e.g. `Template:`, by reading the index again
+    pub fn blocks_containing(&self, title_prefix: &str) -> io::Result<Vec<Block>> {
+        let reader = BufReader::new(MultiBzDecoder::new(File::open(&self.index_path)?));
+
+        let mut blocks: Vec<Block> = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let mut parts = line.splitn(3, ':');
+            let (Some(offset), Some(_), Some(title)) = (parts.next(), parts.next(), parts.next()) else { continue; };
+            if !title.starts_with(title_prefix) {
+                continue;
+            }
+
+            let Ok(offset) = offset.parse::<u64>() else { continue; };
+            if blocks.last().map(|b| b.offset) == Some(offset) {
+                continue;
+            }
+            if let Ok(i) = self.blocks.binary_search_by_key(&offset, |b| b.offset) {
+                blocks.push(self.blocks[i]);
+            }
+        }
+
+        blocks.sort_unstable_by_key(|b| b.offset);
+        blocks.dedup_by_key(|b| b.offset);
+        Ok(blocks)
+    }
+
+    /// Reads the still-compressed bytes of `block`
+    pub fn read_block(&mut self, block: &Block) -> io::Result<Vec<u8>> {
+        let mut compressed = Vec::with_capacity(block.len as usize);
+        self.file.seek(SeekFrom::Start(block.offset))?;
+        (&mut self.file).take(block.len).read_to_end(&mut compressed)?;
+        Ok(compressed)
+    }
+
+    /// Returns a reader over the decompressed text of the header followed by every page block,
+    /// decompressing one stream at a time
+    pub fn reader(self) -> MultistreamReader {
+        let mut blocks = Vec::with_capacity(self.blocks.len() + 1);
+        blocks.push(self.header);
+        blocks.extend_from_slice(&self.blocks);
+
+        MultistreamReader {
+            file: self.file,
+            blocks,
+            next_block: 0,
+            current: None,
+        }
+    }
+}
+
+/// Decompresses a single bzip2 stream read with [`MultistreamDump::read_block`]
+pub fn decompress_block(compressed: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::with_capacity(compressed.len() * 5);
+    BzDecoder::new(compressed).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+pub struct MultistreamReader {
+    file: File,
+    blocks: Vec<Block>,
+    next_block: usize,
+    current: Option<BzDecoder<Take<File>>>,
+}
+
+impl Read for MultistreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(decoder) = &mut self.current {
+                let read = decoder.read(buf)?;
+                if read != 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+                self.current = None;
+            }
+
+            let Some(block) = self.blocks.get(self.next_block) else { return Ok(0); };
+            self.next_block += 1;
+
+            let mut file = self.file.try_clone()?;
+            file.seek(SeekFrom::Start(block.offset))?;
+            self.current = Some(BzDecoder::new(file.take(block.len)));
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use rusqlite::Connection;
+
+/// Aliases MediaWiki accepts for namespaces but doesn't list in `<siteinfo>`
+const ALIASES: [(&str, i32); 6] = [
+    ("WP", 4),
+    ("WT", 5),
+    ("Project", 4),
+    ("Project talk", 5),
+    ("Image", 6),
+    ("Image talk", 7),
+];
+
+pub struct Namespace {
+    pub name: String,
+    /// `case="first-letter"`: the first letter of titles is always uppercase.
+    /// Otherwise the namespace is `case-sensitive`.
+    pub first_letter: bool,
+}
+
+/// The namespaces of a wiki, as listed in the `<siteinfo><namespaces>` block at the start of a dump
+pub struct Namespaces {
+    namespaces: HashMap<i32, Namespace>,
+    ids: HashMap<String, i32>,
+}
+
+impl Namespaces {
+    /// Parses lines like `<namespace key="14" case="first-letter">Category</namespace>`.
+    /// The main namespace is written as a self-closing element without a name.
+    pub fn from_siteinfo(siteinfo: &str) -> Namespaces {
+        const NAMESPACE_TAG: &str = "<namespace ";
+        const KEY_ATTRIBUTE: &str = "key=\"";
+        const CASE_SENSITIVE_ATTRIBUTE: &str = "case=\"case-sensitive\"";
+        const END_NAMESPACE_TAG: &str = "</namespace>";
+
+        let mut namespaces = HashMap::new();
+        for line in siteinfo.lines() {
+            let line = line.trim();
+            if !line.starts_with(NAMESPACE_TAG) {
+                continue;
+            }
+
+            let Some(key_start) = line.find(KEY_ATTRIBUTE).map(|p| p + KEY_ATTRIBUTE.len()) else { continue; };
+            let Some(key_len) = line[key_start..].find('"') else { continue; };
+            let Ok(id) = line[key_start..key_start + key_len].parse::<i32>() else { continue; };
+
+            let name = match (line.find('>'), line.strip_suffix(END_NAMESPACE_TAG)) {
+                (Some(start), Some(line)) if !line.ends_with('/') => &line[start + 1..],
+                _ => ""
+            };
+
+            namespaces.insert(id, Namespace {
+                name: name.to_string(),
+                first_letter: !line.contains(CASE_SENSITIVE_ATTRIBUTE),
+            });
+        }
+
+        Self::from_namespaces(namespaces)
+    }
+
+    /// The namespaces of English Wikipedia, for databases built without siteinfo
+    pub fn enwiki() -> Namespaces {
+        Self::from_namespaces(HashMap::from([
+            (-2, "Media"), (-1, "Special"), (0, ""), (1, "Talk"), (2, "User"), (3, "User talk"),
+            (4, "Wikipedia"), (5, "Wikipedia talk"), (6, "File"), (7, "File talk"), (8, "MediaWiki"),
+            (9, "MediaWiki talk"), (10, "Template"), (11, "Template talk"), (12, "Help"), (13, "Help talk"),
+            (14, "Category"), (15, "Category talk"), (100, "Portal"), (101, "Portal talk"), (118, "Draft"),
+            (119, "Draft talk"), (710, "TimedText"), (711, "TimedText talk"), (828, "Module"), (829, "Module talk"),
+        ].map(|(id, name)| (id, Namespace { name: name.to_string(), first_letter: true }))))
+    }
+
+    /// Loads the namespaces saved with [`Namespaces::save`], or falls back to [`Namespaces::enwiki`]
+    /// for databases built before they were recorded
+    pub fn load(conn: &Connection) -> Namespaces {
+        let namespaces = conn.prepare("SELECT id, name, first_letter FROM namespaces")
+            .and_then(|mut statement| {
+                statement.query_map((), |row| Ok((row.get(0)?, Namespace {
+                    name: row.get(1)?,
+                    first_letter: row.get(2)?,
+                })))?.collect::<Result<HashMap<i32, Namespace>, _>>()
+            });
+
+        match namespaces {
+            Ok(namespaces) if !namespaces.is_empty() => Self::from_namespaces(namespaces),
+            _ => {
+                println!("No namespaces recorded in database - assuming English Wikipedia");
+                Self::enwiki()
+            }
+        }
+    }
+
+    pub fn save(&self, conn: &Connection) -> rusqlite::Result<()> {
+        conn.execute("DROP TABLE IF EXISTS namespaces", ())?;
+        conn.execute(
+            "CREATE TABLE namespaces (
+            id INTEGER PRIMARY KEY,
+            name TEXT,
+            first_letter INTEGER
+         )",
+            ()
+        )?;
+
+        let mut statement = conn.prepare("INSERT INTO namespaces VALUES (?, ?, ?)")?;
+        for (id, namespace) in &self.namespaces {
+            statement.execute((id, &namespace.name, namespace.first_letter))?;
+        }
+        Ok(())
+    }
+
+    fn from_namespaces(namespaces: HashMap<i32, Namespace>) -> Namespaces {
+        let mut ids: HashMap<String, i32> = namespaces.iter()
+            .filter(|(_, namespace)| !namespace.name.is_empty())
+            .map(|(id, namespace)| (namespace.name.to_lowercase(), *id))
+            .collect();
+
+        for (alias, id) in ALIASES {
+            if namespaces.contains_key(&id) {
+                ids.entry(alias.to_lowercase()).or_insert(id);
+            }
+        }
+
+        Namespaces { namespaces, ids }
+    }
+
+    pub fn get(&self, id: i32) -> Option<&Namespace> {
+        self.namespaces.get(&id)
+    }
+
+    pub fn name(&self, id: i32) -> Option<&str> {
+        self.get(id).map(|n| n.name.as_str())
+    }
+
+    /// Looks up a namespace by name or alias, ignoring case and treating underscores as spaces
+    pub fn id(&self, name: &str) -> Option<i32> {
+        self.ids.get(&name.trim().replace('_', " ").to_lowercase()).copied()
+    }
+
+    /// Returns the namespace a link target points into. A leading `:` (as in `[[:Category:Foo]]`)
+    /// is ignored and anything without a known prefix is in the main namespace.
+    pub fn namespace_of(&self, target: &str) -> i32 {
+        let target = target.trim_start().strip_prefix(':').unwrap_or(target);
+        target.split_once(':')
+            .and_then(|(prefix, _)| self.id(prefix))
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,292 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+use hhmmss::Hhmmss;
+use rusqlite::Connection;
+
+/// Creates the id-based tables from the `page_references` staging table and then drops it:
+/// - `pages(id, title, is_redirect, namespace)` with ids counting up from 0 in dump order
+/// - `links(src_id, dst_id, ordinal)` with each page's distinct link targets in page order. A
+///   redirect has a single link to its target.
+/// - `unresolved_links(src_id, ordinal, target)` for targets with no page
+/// - `backlinks(dst_id, src_id, ordinal)`, the `links` table keyed by target for "what links here"
+///
+/// and then resolves redirects with [`resolve_redirects`]. Everything happens in one transaction,
+/// so either all the tables exist or none do.
+pub fn build_link_tables(conn: &Connection) -> rusqlite::Result<LinkTableStats> {
+    let start = Instant::now();
+    // Rolled back when dropped, so an error leaves the connection as it was
+    let transaction = conn.unchecked_transaction()?;
+
+    conn.execute_batch(
+        "DROP TABLE IF EXISTS pages;
+         DROP TABLE IF EXISTS links;
+         DROP TABLE IF EXISTS unresolved_links;
+         DROP TABLE IF EXISTS backlinks;
+         CREATE TABLE pages (
+            id INTEGER PRIMARY KEY,
+            title TEXT UNIQUE NOT NULL,
+            is_redirect INTEGER,
+            namespace INTEGER,
+            resolved_target INTEGER,
+            redirect_status TEXT,
+            redirect_chain INTEGER
+         );
+         CREATE TABLE unresolved_links (
+            src_id INTEGER,
+            ordinal INTEGER,
+            target TEXT,
+            PRIMARY KEY (src_id, ordinal)
+         ) WITHOUT ROWID;
+         INSERT INTO pages (id, title, is_redirect, namespace)
+            SELECT rowid - 1, title, is_redirect, namespace FROM page_references ORDER BY rowid;"
+    )?;
+    create_links_table(conn)?;
+
+    let mut ids: HashMap<String, i64> = HashMap::new();
+    {
+        let mut statement = conn.prepare("SELECT id, title FROM pages")?;
+        let mut rows = statement.query(())?;
+        while let Some(row) = rows.next()? {
+            ids.insert(row.get(1)?, row.get(0)?);
+        }
+    }
+    println!("Loaded {} page ids in {}", ids.len(), start.elapsed().hhmmss());
+
+    let mut stats = LinkTableStats { pages: ids.len(), links: 0, unresolved: 0, redirects: RedirectStats::default() };
+    {
+        let mut insert_link = conn.prepare("INSERT INTO links VALUES (?, ?, ?)")?;
+        let mut insert_unresolved = conn.prepare("INSERT INTO unresolved_links VALUES (?, ?, ?)")?;
+
+        let mut statement = conn.prepare("SELECT rowid - 1, links FROM page_references ORDER BY rowid")?;
+        let mut rows = statement.query(())?;
+        let mut count: u32 = 0;
+        while let Some(row) = rows.next()? {
+            let src_id: i64 = row.get(0)?;
+            let links: String = row.get(1)?;
+
+            let mut seen = HashSet::new();
+            for link in links.split("<|>").filter(|link| !link.is_empty()) {
+                let ordinal = seen.len();
+                if !seen.insert(link) {
+                    continue;
+                }
+
+                match ids.get(link) {
+                    Some(dst_id) => {
+                        insert_link.execute((src_id, dst_id, ordinal))?;
+                        stats.links += 1;
+                    }
+                    None => {
+                        insert_unresolved.execute((src_id, ordinal, link))?;
+                        stats.unresolved += 1;
+                    }
+                }
+            }
+
+            count += 1;
+            if count.is_multiple_of(1_000_000) {
+                println!("Resolved the links of {} pages in {}", count, start.elapsed().hhmmss());
+            }
+        }
+    }
+
+    build_backlinks(conn)?;
+    println!("Built backlinks in {}", start.elapsed().hhmmss());
+
+    conn.execute("DROP TABLE page_references", ())?;
+    stats.redirects = resolve_redirects(conn)?;
+    transaction.commit()?;
+    Ok(stats)
+}
+
+pub fn create_links_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE links (
+            src_id INTEGER,
+            dst_id INTEGER,
+            ordinal INTEGER,
+            PRIMARY KEY (src_id, ordinal)
+         ) WITHOUT ROWID;"
+    )
+}
+
+/// Creates `backlinks(dst_id, src_id, ordinal)` from the `links` table
+pub fn build_backlinks(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "DROP TABLE IF EXISTS backlinks;
+         CREATE TABLE backlinks (
+            dst_id INTEGER,
+            src_id INTEGER,
+            ordinal INTEGER,
+            PRIMARY KEY (dst_id, src_id)
+         ) WITHOUT ROWID;
+         INSERT INTO backlinks SELECT dst_id, src_id, ordinal FROM links ORDER BY dst_id, src_id;"
+    )
+}
+
+pub struct LinkTableStats {
+    pub pages: usize,
+    pub links: usize,
+    pub unresolved: usize,
+    pub redirects: RedirectStats,
+}
+
+/// `redirect_status` of a redirect whose chain ends at a page that isn't a redirect
+pub const REDIRECT_OK: &str = "ok";
+/// `redirect_status` of a redirect whose chain ends at a missing page
+pub const REDIRECT_BROKEN: &str = "broken";
+/// `redirect_status` of a redirect whose chain comes back to a redirect it already passed
+pub const REDIRECT_LOOP: &str = "loop";
+
+#[derive(Default)]
+pub struct RedirectStats {
+    pub redirects: usize,
+    pub broken: usize,
+    pub loops: usize,
+    /// Redirects that only reach a page through another redirect
+    pub double: usize,
+}
+
+/// Follows every redirect chain in `pages` once and fills in
+/// - `resolved_target`: the id of the page at the end of the chain, NULL if broken or looping
+/// - `redirect_status`: [`REDIRECT_OK`], [`REDIRECT_BROKEN`] or [`REDIRECT_LOOP`]
+/// - `redirect_chain`: how many redirects were followed, 1 for a plain redirect
+///
+/// Double redirects and loops are listed in `redirect_reports(id, kind, chain_length, chain)`
+/// with the titles along the chain.
+pub fn resolve_redirects(conn: &Connection) -> rusqlite::Result<RedirectStats> {
+    let mut targets: HashMap<i64, Option<i64>> = HashMap::new();
+    {
+        let mut statement = conn.prepare(
+            "SELECT pages.id, links.dst_id FROM pages
+             LEFT JOIN links ON links.src_id = pages.id
+             WHERE pages.is_redirect"
+        )?;
+        let mut rows = statement.query(())?;
+        while let Some(row) = rows.next()? {
+            targets.insert(row.get(0)?, row.get(1)?);
+        }
+    }
+
+    conn.execute_batch(
+        "UPDATE pages SET resolved_target = NULL, redirect_status = NULL, redirect_chain = NULL;
+         DROP TABLE IF EXISTS redirect_reports;
+         CREATE TABLE redirect_reports (
+            id INTEGER,
+            kind TEXT,
+            chain_length INTEGER,
+            chain TEXT
+         );"
+    )?;
+
+    let mut update = conn.prepare("UPDATE pages SET resolved_target = ?, redirect_status = ?, redirect_chain = ? WHERE id = ?")?;
+    let mut report = conn.prepare("INSERT INTO redirect_reports VALUES (?, ?, ?, ?)")?;
+    let mut title = conn.prepare("SELECT title FROM pages WHERE id = ?")?;
+
+    let mut ids: Vec<i64> = targets.keys().copied().collect();
+    ids.sort();
+
+    let mut stats = RedirectStats { redirects: ids.len(), ..RedirectStats::default() };
+    for id in ids {
+        let mut chain = vec![id];
+        let (resolved, status) = loop {
+            match targets.get(chain.last().unwrap()) {
+                None => break (chain.last().copied(), REDIRECT_OK),
+                Some(None) => break (None, REDIRECT_BROKEN),
+                Some(Some(next)) => {
+                    let looped = chain.contains(next);
+                    chain.push(*next);
+                    if looped {
+                        break (None, REDIRECT_LOOP);
+                    }
+                }
+            }
+        };
+
+        // A broken chain ends with a hop to the missing page, which isn't in `chain`
+        let chain_length = if status == REDIRECT_BROKEN { chain.len() } else { chain.len() - 1 };
+        update.execute((resolved, status, chain_length, id))?;
+
+        let kind = match status {
+            REDIRECT_BROKEN => { stats.broken += 1; continue; }
+            REDIRECT_LOOP => { stats.loops += 1; "loop" }
+            _ if chain_length > 1 => { stats.double += 1; "double" }
+            _ => continue
+        };
+
+        let titles = chain.iter()
+            .map(|id| title.query_row((id,), |row| row.get(0)))
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        report.execute((id, kind, chain_length, titles.join(" -> ")))?;
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database with `page_references` staged like `process_data_no_xml` leaves it
+    fn staged(pages: &[(&str, &str, bool)]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE page_references (title TEXT PRIMARY KEY, links TEXT, is_redirect INTEGER, namespace INTEGER)", ()).unwrap();
+        for (title, links, is_redirect) in pages {
+            conn.execute("INSERT INTO page_references VALUES (?, ?, ?, 0)", (title, links, is_redirect)).unwrap();
+        }
+        conn
+    }
+
+    /// Runs a query whose single column is text, e.g. a row formatted with `||`
+    fn strings(conn: &Connection, sql: &str) -> Vec<String> {
+        let mut statement = conn.prepare(sql).unwrap();
+        statement.query_map((), |row| row.get(0)).unwrap().map(|row| row.unwrap()).collect()
+    }
+
+    #[test]
+    fn pages_are_numbered_in_dump_order() {
+        let conn = staged(&[("Zeta", "", false), ("Alpha", "Zeta", false), ("Old", "Alpha", true)]);
+        let stats = build_link_tables(&conn).unwrap();
+        assert_eq!((stats.pages, stats.links, stats.unresolved), (3, 2, 0));
+        assert_eq!(strings(&conn, "SELECT id || ' ' || title || ' ' || is_redirect FROM pages ORDER BY id"), ["0 Zeta 0", "1 Alpha 0", "2 Old 1"]);
+
+        let staging: i64 = conn.query_row("SELECT count(*) FROM sqlite_master WHERE name = 'page_references'", (), |row| row.get(0)).unwrap();
+        assert_eq!(staging, 0);
+    }
+
+    #[test]
+    fn links_keep_their_first_position_and_unresolved_targets_are_kept_apart() {
+        let conn = staged(&[("A", "B<|>Missing<|>B<|>C<|><|>A", false), ("B", "", false), ("C", "Gone", false)]);
+        let stats = build_link_tables(&conn).unwrap();
+        assert_eq!((stats.pages, stats.links, stats.unresolved), (3, 3, 2));
+        assert_eq!(strings(&conn, "SELECT src_id || ' ' || ordinal || ' ' || dst_id FROM links ORDER BY src_id, ordinal"), ["0 0 1", "0 2 2", "0 3 0"]);
+        assert_eq!(strings(&conn, "SELECT src_id || ' ' || ordinal || ' ' || target FROM unresolved_links ORDER BY src_id, ordinal"), ["0 1 Missing", "2 0 Gone"]);
+        assert_eq!(strings(&conn, "SELECT dst_id || ' ' || src_id || ' ' || ordinal FROM backlinks"), ["0 0 3", "1 0 0", "2 0 2"]);
+    }
+
+    #[test]
+    fn redirect_chains_are_followed_to_the_end() {
+        let conn = staged(&[
+            ("Bedford", "Luton", false),
+            ("Luton", "", false),
+            ("Beds", "Bedford", true),
+            ("Bedfordshire town", "Beds", true),
+            ("Loop A", "Loop B", true),
+            ("Loop B", "Loop A", true),
+            ("Broken", "Nowhere", true),
+            ("Via broken", "Broken", true),
+        ]);
+        let redirects = build_link_tables(&conn).unwrap().redirects;
+        assert_eq!((redirects.redirects, redirects.broken, redirects.loops, redirects.double), (6, 2, 2, 1));
+
+        assert_eq!(strings(&conn, "SELECT title || ' ' || coalesce(resolved_target, '-') || ' ' || coalesce(redirect_status, '-') FROM pages ORDER BY id"), [
+            "Bedford - -", "Luton - -", "Beds 0 ok", "Bedfordshire town 0 ok", "Loop A - loop", "Loop B - loop", "Broken - broken", "Via broken - broken",
+        ]);
+        assert_eq!(strings(&conn, "SELECT title || ' ' || redirect_chain FROM pages WHERE is_redirect ORDER BY id"), [
+            "Beds 1", "Bedfordshire town 2", "Loop A 2", "Loop B 2", "Broken 1", "Via broken 2",
+        ]);
+        assert_eq!(strings(&conn, "SELECT kind || ' ' || chain_length || ' ' || chain FROM redirect_reports ORDER BY id"), [
+            "double 2 Bedfordshire town -> Beds -> Bedford", "loop 2 Loop A -> Loop B -> Loop A", "loop 2 Loop B -> Loop A -> Loop B",
+        ]);
+    }
+}
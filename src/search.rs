@@ -0,0 +1,1294 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::time::Instant;
+use either::Either;
+use num_format::{Locale, ToFormattedString};
+use regex::Regex;
+use crate::graph::{LinkGraph, PageId};
+
+const UNVISITED: PageId = PageId::MAX;
+
+/// One page on a path, and whether it was reached by following a redirect
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub id: PageId,
+    pub from_redirect: bool,
+}
+
+pub struct SearchResult {
+    /// The path from the start to the target, if one was found
+    pub path: Option<Vec<Step>>,
+    /// Pages taken off the open set
+    pub searched: u32,
+    pub visited: usize,
+    pub open_set: usize,
+}
+
+/// Breadth-first search from `start` for `target`. A redirect leads straight to its resolved
+/// target without counting as a step, so a page and the redirects pointing at it are the same
+/// distance away. Broken and looping redirects are dead ends.
+/// Without a target the whole reachable graph is searched.
+pub fn bfs(graph: &impl LinkGraph, start: PageId, target: Option<PageId>) -> SearchResult {
+    let start_time = Instant::now();
+    let mut parents = vec![UNVISITED; graph.len()];
+    let mut from_redirect = vec![false; graph.len()];
+    parents[start as usize] = start;
+    let mut visited: usize = 1;
+
+    let mut open_set = VecDeque::with_capacity(1_000_000);
+    let mut reached = vec![start];
+
+    let mut searched: u32 = 0;
+    let mut found = None;
+
+    'main_loop: loop {
+        // A redirect is followed as soon as it's reached, so its target gets the same distance
+        for id in reached.drain(..) {
+            if Some(id) == target {
+                found = Some(id);
+                break 'main_loop;
+            }
+            open_set.push_back(id);
+
+            let Some(canonical) = graph.resolved_target(id) else { continue; };
+            if parents[canonical as usize] != UNVISITED {
+                continue;
+            }
+            parents[canonical as usize] = id;
+            from_redirect[canonical as usize] = true;
+            visited += 1;
+
+            if Some(canonical) == target {
+                found = Some(canonical);
+                break 'main_loop;
+            }
+            open_set.push_back(canonical);
+        }
+
+        let Some(id) = open_set.pop_front() else { break; };
+        searched += 1;
+        if searched.is_multiple_of(100_000) {
+            print_progress(searched, start_time, visited, open_set.len());
+        }
+
+        // A redirect's only link is to its target, which was reached along with it
+        if graph.is_redirect(id) {
+            continue;
+        }
+
+        for link in graph.links(id) {
+            if parents[link as usize] != UNVISITED {
+                continue;
+            }
+            parents[link as usize] = id;
+            visited += 1;
+            reached.push(link);
+        }
+    }
+
+    let path = found.map(|found| {
+        let mut path = vec![Step { id: found, from_redirect: from_redirect[found as usize] }];
+        while path.last().unwrap().id != start {
+            let parent = parents[path.last().unwrap().id as usize];
+            path.push(Step { id: parent, from_redirect: from_redirect[parent as usize] });
+        }
+        path.reverse();
+        path[0].from_redirect = false;
+        path
+    });
+
+    SearchResult { path, searched, visited, open_set: open_set.len() }
+}
+
+/// One direction of [`bidirectional_bfs`]. For the forward side `links[id]` is the page `id` was
+/// reached from, for the backward side the page `id` leads to.
+struct Side {
+    links: Vec<PageId>,
+    from_redirect: Vec<bool>,
+    depths: Vec<u32>,
+    frontier: Vec<PageId>,
+    depth: u32,
+    visited: usize,
+}
+
+impl Side {
+    fn new(len: usize) -> Side {
+        Side { links: vec![UNVISITED; len], from_redirect: vec![false; len], depths: vec![0; len], frontier: Vec::new(), depth: 0, visited: 0 }
+    }
+
+    fn is_visited(&self, id: PageId) -> bool {
+        self.links[id as usize] != UNVISITED
+    }
+
+    /// Marks `id` as reached through `link`, returning false if it already was
+    fn visit(&mut self, id: PageId, link: PageId, from_redirect: bool, next: &mut Vec<PageId>) -> bool {
+        if self.is_visited(id) {
+            return false;
+        }
+        self.links[id as usize] = link;
+        self.from_redirect[id as usize] = from_redirect;
+        self.depths[id as usize] = self.depth;
+        self.visited += 1;
+        next.push(id);
+        true
+    }
+}
+
+/// Finds a shortest path from `start` to `target` by searching forward from `start` and backward
+/// over backlinks from `target` a level at a time, always growing the smaller frontier. The paths
+/// it finds are as long as the ones [`bfs`] finds, with redirects handled the same way: forward,
+/// a redirect leads straight to its resolved target; backward, a page is reached for free from
+/// every redirect that resolves to it, and redirects are only reached from pages that link to them.
+pub fn bidirectional_bfs(graph: &impl LinkGraph, start: PageId, target: PageId) -> SearchResult {
+    let start_time = Instant::now();
+    let mut forward = Side::new(graph.len());
+    let mut backward = Side::new(graph.len());
+
+    let mut next = Vec::new();
+    discover_forward(graph, &mut forward, start, start, false, &mut next);
+    forward.frontier = next;
+    let mut next = Vec::new();
+    discover_backward(graph, &mut backward, target, target, false, &mut next);
+    backward.frontier = next;
+
+    let mut searched: u32 = 0;
+    // The meeting page with the shortest path through it
+    let mut meeting = forward.frontier.iter().copied().find(|id| backward.is_visited(*id));
+
+    while meeting.is_none() && !forward.frontier.is_empty() && !backward.frontier.is_empty() {
+        let is_forward = forward.frontier.len() <= backward.frontier.len();
+        let (side, other) = if is_forward { (&mut forward, &backward) } else { (&mut backward, &forward) };
+        side.depth += 1;
+
+        let mut next = Vec::new();
+        for id in std::mem::take(&mut side.frontier) {
+            searched += 1;
+            if searched.is_multiple_of(100_000) {
+                print_progress(searched, start_time, side.visited + other.visited, next.len());
+            }
+
+            // A redirect's only link is to its target, which was reached along with it
+            if is_forward && graph.is_redirect(id) {
+                continue;
+            }
+            let linked = if is_forward {
+                Either::Left(graph.links(id))
+            } else {
+                Either::Right(graph.backlinks(id).filter(|src| !graph.is_redirect(*src)))
+            };
+
+            let first_new = next.len();
+            for link in linked {
+                if is_forward {
+                    discover_forward(graph, side, link, id, false, &mut next);
+                } else {
+                    discover_backward(graph, side, link, id, false, &mut next);
+                }
+            }
+
+            for reached in &next[first_new..] {
+                if !other.is_visited(*reached) {
+                    continue;
+                }
+                let length = |id: PageId| side.depths[id as usize] + other.depths[id as usize];
+                if meeting.is_none_or(|meeting| length(*reached) < length(meeting)) {
+                    meeting = Some(*reached);
+                }
+            }
+        }
+        side.frontier = next;
+    }
+
+    let path = meeting.map(|meeting| {
+        let mut path = vec![Step { id: meeting, from_redirect: forward.from_redirect[meeting as usize] }];
+        while path.last().unwrap().id != start {
+            let parent = forward.links[path.last().unwrap().id as usize];
+            path.push(Step { id: parent, from_redirect: forward.from_redirect[parent as usize] });
+        }
+        path.reverse();
+        path[0].from_redirect = false;
+
+        let mut id = meeting;
+        while id != target {
+            let from_redirect = backward.from_redirect[id as usize];
+            id = backward.links[id as usize];
+            path.push(Step { id, from_redirect });
+        }
+        path
+    });
+
+    SearchResult {
+        path,
+        searched,
+        visited: forward.visited + backward.visited,
+        open_set: forward.frontier.len() + backward.frontier.len(),
+    }
+}
+
+/// Visits `id` from `parent`, and the page a redirect resolves to along with it
+fn discover_forward(graph: &impl LinkGraph, side: &mut Side, id: PageId, parent: PageId, from_redirect: bool, next: &mut Vec<PageId>) {
+    if side.visit(id, parent, from_redirect, next) && graph.is_redirect(id) {
+        if let Some(canonical) = graph.resolved_target(id) {
+            discover_forward(graph, side, canonical, id, true, next);
+        }
+    }
+}
+
+/// Visits `id` as leading to `child`, and every redirect that resolves to it along with it
+fn discover_backward(graph: &impl LinkGraph, side: &mut Side, id: PageId, child: PageId, from_redirect: bool, next: &mut Vec<PageId>) {
+    if !side.visit(id, child, from_redirect, next) || graph.is_redirect(id) {
+        return;
+    }
+
+    // A double redirect resolves to `id` too, so follow redirects to redirects
+    let mut redirects = graph.redirects_to(id);
+    while let Some(redirect) = redirects.pop() {
+        if side.visit(redirect, id, true, next) {
+            redirects.extend(graph.redirects_to(redirect));
+        }
+    }
+}
+
+/// Every shortest path between two pages, kept as the layered DAG of the BFS that found them
+pub struct ShortestPaths {
+    pub start: PageId,
+    pub target: PageId,
+    /// Number of links on each path, `None` if the target can't be reached
+    pub length: Option<u32>,
+    /// The pages on at least one shortest path, by distance from the start. Within a layer,
+    /// redirects come before the pages they resolve to.
+    pub layers: Vec<Vec<PageId>>,
+    /// The pages one step before each page on a shortest path
+    pub parents: HashMap<PageId, Vec<PageId>>,
+    /// How many shortest paths lead from the start to each page, saturating at `u128::MAX`
+    pub paths_to: HashMap<PageId, u128>,
+    /// How many shortest paths lead from each page to the target
+    pub paths_from: HashMap<PageId, u128>,
+    redirects: HashSet<PageId>,
+    pub searched: u32,
+    pub visited: usize,
+}
+
+impl ShortestPaths {
+    pub fn total(&self) -> u128 {
+        self.paths_to.get(&self.target).copied().unwrap_or(0)
+    }
+
+    /// How many shortest paths go through `id`
+    pub fn paths_through(&self, id: PageId) -> u128 {
+        self.paths_to.get(&id).unwrap_or(&0).saturating_mul(*self.paths_from.get(&id).unwrap_or(&0))
+    }
+
+    /// The pages every shortest path goes through, in path order, including both ends
+    pub fn unavoidable(&self) -> Vec<PageId> {
+        self.layers.iter().flatten().copied().filter(|id| self.paths_through(*id) == self.total()).collect()
+    }
+
+    /// Up to `max` of the shortest paths
+    pub fn paths(&self, max: usize) -> Vec<Vec<Step>> {
+        let mut paths = Vec::new();
+        if self.length.is_some() {
+            self.collect_paths(&mut vec![self.target], &mut paths, max);
+        }
+        paths
+    }
+
+    /// Extends `suffix`, which runs backwards from the target, with each parent of its last page
+    fn collect_paths(&self, suffix: &mut Vec<PageId>, paths: &mut Vec<Vec<Step>>, max: usize) {
+        let id = *suffix.last().unwrap();
+        if id == self.start {
+            let mut path = Vec::with_capacity(suffix.len());
+            for (i, id) in suffix.iter().rev().enumerate() {
+                let from_redirect = i > 0 && self.redirects.contains(&suffix[suffix.len() - i]);
+                path.push(Step { id: *id, from_redirect });
+            }
+            paths.push(path);
+            return;
+        }
+
+        for parent in &self.parents[&id] {
+            if paths.len() >= max {
+                return;
+            }
+            suffix.push(*parent);
+            self.collect_paths(suffix, paths, max);
+            suffix.pop();
+        }
+    }
+}
+
+/// Breadth-first search from `start` that finishes the layer `target` is found in, keeping every
+/// page's parents on the layer before rather than just the first. Redirects are followed as in
+/// [`bfs`]. The paths are counted through the resulting DAG instead of being listed, so
+/// [`ShortestPaths::total`] is cheap even when there are millions.
+pub fn all_shortest_paths(graph: &impl LinkGraph, start: PageId, target: PageId) -> ShortestPaths {
+    let start_time = Instant::now();
+    let mut depths = vec![UNVISITED; graph.len()];
+    let mut parents: HashMap<PageId, Vec<PageId>> = HashMap::new();
+    let mut visited = 0;
+    let mut searched: u32 = 0;
+
+    let mut depth = 0;
+    let mut layer = Vec::new();
+    reach(graph, &mut depths, &mut parents, start, None, depth, &mut layer);
+    visited += layer.len();
+
+    while depths[target as usize] == UNVISITED && !layer.is_empty() {
+        depth += 1;
+        let mut next = Vec::new();
+        for id in layer {
+            searched += 1;
+            if searched.is_multiple_of(100_000) {
+                print_progress(searched, start_time, visited + next.len(), next.len());
+            }
+            if graph.is_redirect(id) {
+                continue;
+            }
+            for link in graph.links(id) {
+                reach(graph, &mut depths, &mut parents, link, Some(id), depth, &mut next);
+            }
+        }
+        visited += next.len();
+        layer = next;
+    }
+
+    let mut paths = ShortestPaths {
+        start, target, length: None, layers: Vec::new(), parents: HashMap::new(), paths_to: HashMap::new(),
+        paths_from: HashMap::new(), redirects: HashSet::new(), searched, visited,
+    };
+    if depths[target as usize] == UNVISITED {
+        return paths;
+    }
+
+    // Keep only the pages with a shortest path on to the target
+    let mut on_path = vec![target];
+    let mut seen = HashSet::from([target]);
+    let mut i = 0;
+    while i < on_path.len() {
+        let id = on_path[i];
+        i += 1;
+        if id == start {
+            continue;
+        }
+        let page_parents = parents.remove(&id).unwrap();
+        on_path.extend(page_parents.iter().copied().filter(|parent| seen.insert(*parent)));
+        paths.parents.insert(id, page_parents);
+    }
+
+    paths.redirects = on_path.iter().copied().filter(|id| graph.is_redirect(*id)).collect();
+    on_path.sort_by_key(|id| (depths[*id as usize], !paths.redirects.contains(id)));
+    paths.layers = vec![Vec::new(); depth as usize + 1];
+    for id in &on_path {
+        paths.layers[depths[*id as usize] as usize].push(*id);
+    }
+
+    for id in &on_path {
+        let count = match paths.parents.get(id) {
+            Some(parents) => parents.iter().fold(0u128, |count, parent| count.saturating_add(paths.paths_to[parent])),
+            None => 1
+        };
+        paths.paths_to.insert(*id, count);
+    }
+    paths.paths_from.insert(target, 1);
+    for id in on_path.iter().rev() {
+        let count = paths.paths_from[id];
+        for parent in paths.parents.get(id).into_iter().flatten() {
+            let from = paths.paths_from.entry(*parent).or_insert(0);
+            *from = from.saturating_add(count);
+        }
+    }
+
+    // Redirects are free, so the length counts only the links between pages
+    paths.length = Some(depth);
+    paths
+}
+
+/// Reaches `id` at `depth`, recording `parent` if this is one of its shortest routes, and
+/// reaches the page a redirect resolves to at the same depth
+fn reach(graph: &impl LinkGraph, depths: &mut [u32], parents: &mut HashMap<PageId, Vec<PageId>>, id: PageId, parent: Option<PageId>, depth: u32, next: &mut Vec<PageId>) {
+    let first_visit = depths[id as usize] == UNVISITED;
+    if !first_visit && depths[id as usize] != depth {
+        return;
+    }
+    if let Some(parent) = parent {
+        parents.entry(id).or_default().push(parent);
+    }
+    if !first_visit {
+        return;
+    }
+
+    depths[id as usize] = depth;
+    next.push(id);
+    if let Some(canonical) = graph.resolved_target(id) {
+        reach(graph, depths, parents, canonical, Some(id), depth, next);
+    }
+}
+
+/// Several paths between the same two pages, shortest first
+pub struct AlternativePaths {
+    pub paths: Vec<Vec<Step>>,
+    /// Pages taken off the open set, over every search that was needed
+    pub searched: u32,
+    pub visited: usize,
+}
+
+/// The number of links on a path. Following a redirect doesn't count.
+pub fn path_length(path: &[Step]) -> u32 {
+    path.iter().skip(1).filter(|step| !step.from_redirect).count() as u32
+}
+
+/// Yen's algorithm: up to `k` loopless paths from `start` to `target` in order of length, the
+/// first being one [`bfs`] would find. Each next path leaves one of the paths found so far at
+/// some page (the spur) and takes the shortest way on from there that doesn't revisit the pages
+/// before the spur or repeat a link already taken from the same route.
+pub fn k_shortest_paths(graph: &impl LinkGraph, start: PageId, target: PageId, k: usize) -> AlternativePaths {
+    let mut result = AlternativePaths { paths: Vec::new(), searched: 0, visited: 0 };
+    if k == 0 {
+        return result;
+    }
+    let Some(first) = restricted_bfs(graph, start, target, &|_| true, &HashSet::new(), u32::MAX, &mut result) else {
+        return result;
+    };
+    result.paths.push(first);
+
+    let mut candidates: Vec<Vec<Step>> = Vec::new();
+    while result.paths.len() < k {
+        let previous = result.paths.last().unwrap().clone();
+        for i in 0..previous.len() - 1 {
+            let root = &previous[..=i];
+            let mut banned_links = HashSet::new();
+            for path in &result.paths {
+                if path.len() > i + 1 && path[..=i].iter().map(|step| step.id).eq(root.iter().map(|step| step.id)) {
+                    banned_links.insert((path[i].id, path[i + 1].id));
+                }
+            }
+            let banned_pages: HashSet<PageId> = root[..i].iter().map(|step| step.id).collect();
+
+            let Some(spur) = restricted_bfs(graph, previous[i].id, target, &|id| !banned_pages.contains(&id), &banned_links, u32::MAX, &mut result) else {
+                continue;
+            };
+            let mut candidate = root.to_vec();
+            candidate.extend(&spur[1..]);
+            if !candidates.contains(&candidate) && !result.paths.contains(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+
+        // Shortest first, and of those the one with fewest redirects
+        let Some(best) = (0..candidates.len()).min_by_key(|i| (path_length(&candidates[*i]), candidates[*i].len())) else {
+            break;
+        };
+        result.paths.push(candidates.swap_remove(best));
+    }
+    result
+}
+
+/// Up to `k` paths from `start` to `target` that share no pages but the two ends: as many as
+/// there are if that's fewer, and of those the set with the fewest links in total.
+///
+/// Taking the shortest path and then the shortest that avoids it can block every other route,
+/// so this is a min-cost flow instead. Each page is split into an in and an out half joined by
+/// an edge only one path can take, and each round finds the cheapest way through the residual
+/// graph, which may reroute the paths found before. Following a redirect costs nothing, as in
+/// [`bfs`].
+pub fn disjoint_paths(graph: &impl LinkGraph, start: PageId, target: PageId, k: usize) -> AlternativePaths {
+    let mut result = AlternativePaths { paths: Vec::new(), searched: 0, visited: 0 };
+    let mut flow = Flow::default();
+    // Dijkstra's reduced costs: how much cheaper than the target each page was to reach, summed
+    // over the rounds so far. Keeps the costs of the residual graph's backward edges positive.
+    let mut potentials: HashMap<(PageId, Half), i64> = HashMap::new();
+
+    let mut found = 0;
+    while found < k {
+        let source = (start, Half::Out);
+        let sink = (target, Half::In);
+        let mut distances = HashMap::from([(source, 0i64)]);
+        let mut parents: HashMap<(PageId, Half), (PageId, Half)> = HashMap::new();
+        let mut done = Vec::new();
+        let mut open_set = BinaryHeap::from([Open { priority: 0.0, id: 0 }]);
+        let mut nodes = vec![source];
+
+        while let Some(Open { priority, id }) = open_set.pop() {
+            let node = nodes[id as usize];
+            let distance = priority as i64;
+            if distance > distances[&node] {
+                continue;
+            }
+            done.push(node);
+            result.searched += 1;
+            if node == sink {
+                break;
+            }
+
+            for (next, cost) in flow.residual(graph, node, start, target) {
+                let reduced = cost - potentials.get(&node).unwrap_or(&0) + potentials.get(&next).unwrap_or(&0);
+                let next_distance = distance + reduced;
+                if distances.get(&next).is_some_and(|known| *known <= next_distance) {
+                    continue;
+                }
+                distances.insert(next, next_distance);
+                parents.insert(next, node);
+                nodes.push(next);
+                open_set.push(Open { priority: next_distance as f64, id: (nodes.len() - 1) as PageId });
+            }
+        }
+        result.visited += distances.len();
+
+        let Some(sink_distance) = distances.get(&sink).copied().filter(|_| done.last() == Some(&sink)) else {
+            break;
+        };
+        for node in &done {
+            *potentials.entry(*node).or_insert(0) += sink_distance - distances[node];
+        }
+
+        let mut node = sink;
+        while node != source {
+            let parent = parents[&node];
+            flow.push(parent, node);
+            node = parent;
+        }
+        found += 1;
+    }
+
+    result.paths = flow.paths(graph, start, target);
+    result.paths.sort_by_key(|path| (path_length(path), path.len()));
+    result
+}
+
+/// Which half of a split page a node of [`disjoint_paths`]'s flow network is. Links leave from
+/// the out half and arrive at the in half.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Half {
+    In,
+    Out,
+}
+
+/// The links and pages the paths of [`disjoint_paths`] use so far
+#[derive(Default)]
+struct Flow {
+    /// The links with a path along them, from each end
+    links_from: HashMap<PageId, Vec<PageId>>,
+    links_to: HashMap<PageId, Vec<PageId>>,
+    /// Pages with a path through them
+    used: HashSet<PageId>,
+}
+
+impl Flow {
+    /// The cost of a link from `from`
+    fn cost(graph: &impl LinkGraph, from: PageId) -> i64 {
+        if graph.is_redirect(from) { 0 } else { 1 }
+    }
+
+    /// The nodes `node` leads to in the residual graph, and what going there costs
+    fn residual(&self, graph: &impl LinkGraph, node: (PageId, Half), start: PageId, target: PageId) -> Vec<((PageId, Half), i64)> {
+        let (id, half) = node;
+        let mut next = Vec::new();
+        match half {
+            Half::In => {
+                if !self.used.contains(&id) {
+                    next.push(((id, Half::Out), 0));
+                }
+                // Back along a link a path takes, undoing it
+                for from in self.links_to.get(&id).into_iter().flatten() {
+                    next.push(((*from, Half::Out), -Flow::cost(graph, *from)));
+                }
+            }
+            Half::Out => {
+                let links = if graph.is_redirect(id) {
+                    Either::Left(graph.resolved_target(id).into_iter())
+                } else {
+                    Either::Right(graph.links(id))
+                };
+                let taken = self.links_from.get(&id);
+                for link in links {
+                    if link != start && !taken.is_some_and(|taken| taken.contains(&link)) {
+                        next.push(((link, Half::In), Flow::cost(graph, id)));
+                    }
+                }
+                if id != start && id != target && self.used.contains(&id) {
+                    next.push(((id, Half::In), 0));
+                }
+            }
+        }
+        next
+    }
+
+    /// Sends one more path along the residual edge `from` -> `to`
+    fn push(&mut self, from: (PageId, Half), to: (PageId, Half)) {
+        match (from, to) {
+            ((id, Half::In), (other, Half::Out)) if id == other => {
+                self.used.insert(id);
+            }
+            ((id, Half::Out), (other, Half::In)) if id == other => {
+                self.used.remove(&id);
+            }
+            ((src, Half::Out), (dst, Half::In)) => {
+                self.links_from.entry(src).or_default().push(dst);
+                self.links_to.entry(dst).or_default().push(src);
+            }
+            ((dst, Half::In), (src, Half::Out)) => {
+                self.links_from.get_mut(&src).unwrap().retain(|id| *id != dst);
+                self.links_to.get_mut(&dst).unwrap().retain(|id| *id != src);
+            }
+            _ => unreachable!("the residual graph has no edge {:?} -> {:?}", from, to)
+        }
+    }
+
+    /// Follows the links the paths take from `start` to `target`
+    fn paths(mut self, graph: &impl LinkGraph, start: PageId, target: PageId) -> Vec<Vec<Step>> {
+        let mut paths = Vec::new();
+        while let Some(first) = self.links_from.get_mut(&start).and_then(|links| links.pop()) {
+            let mut path = vec![Step { id: start, from_redirect: false }, Step { id: first, from_redirect: graph.is_redirect(start) }];
+            while path.last().unwrap().id != target {
+                let id = path.last().unwrap().id;
+                let next = self.links_from.get_mut(&id).and_then(|links| links.pop()).unwrap();
+                path.push(Step { id: next, from_redirect: graph.is_redirect(id) });
+            }
+            paths.push(path);
+        }
+        paths
+    }
+}
+
+/// Rules a path found by [`constrained_search`] has to follow
+#[derive(Default)]
+pub struct Constraints {
+    /// Pages the path may not go through
+    pub excluded: HashSet<PageId>,
+    /// Patterns matched against the titles of the pages the path goes through
+    pub excluded_patterns: Vec<Regex>,
+    /// Only follow links straight to articles, never through a redirect
+    pub no_redirects: bool,
+    /// The most links the path may have
+    pub max_hops: Option<u32>,
+    /// Pages the path must go through, in this order
+    pub waypoints: Vec<PageId>,
+}
+
+impl Constraints {
+    /// Whether the rules let a path go through `id`
+    pub fn allows(&self, graph: &impl LinkGraph, id: PageId) -> bool {
+        if self.excluded.contains(&id) || (self.no_redirects && graph.is_redirect(id)) {
+            return false;
+        }
+        if self.excluded_patterns.is_empty() {
+            return true;
+        }
+        let title = graph.title(id);
+        !self.excluded_patterns.iter().any(|pattern| pattern.is_match(&title))
+    }
+
+    /// One line per rule, for printing with the result
+    pub fn describe(&self, graph: &impl LinkGraph) -> Vec<String> {
+        let titles = |ids: &mut dyn Iterator<Item = &PageId>| ids.map(|id| format!("'{}'", graph.title(*id))).collect::<Vec<_>>();
+        let mut lines = Vec::new();
+        if !self.excluded.is_empty() {
+            let mut excluded = titles(&mut self.excluded.iter());
+            excluded.sort();
+            lines.push(format!("Avoiding {}", excluded.join(", ")));
+        }
+        for pattern in &self.excluded_patterns {
+            lines.push(format!("Avoiding titles matching /{}/", pattern));
+        }
+        if self.no_redirects {
+            lines.push("Not following redirects".to_string());
+        }
+        if let Some(max_hops) = self.max_hops {
+            lines.push(format!("At most {} links", max_hops));
+        }
+        if !self.waypoints.is_empty() {
+            lines.push(format!("Going through {}", titles(&mut self.waypoints.iter()).join(" then ")));
+        }
+        lines
+    }
+}
+
+/// A shortest path from `start` to `target` that follows `constraints`. With waypoints, it's the
+/// shortest path to each waypoint in turn that doesn't go back through a page the path has
+/// already been to, so it can be longer than the shortest path through all of them, or missed
+/// when every way on from a waypoint goes back that way. The waypoints and the target are
+/// reached even if a rule would exclude them.
+pub fn constrained_search(graph: &impl LinkGraph, start: PageId, target: PageId, constraints: &Constraints) -> SearchResult {
+    let mut result = AlternativePaths { paths: Vec::new(), searched: 0, visited: 0 };
+    let stops: Vec<PageId> = constraints.waypoints.iter().copied().chain([target]).collect();
+    let mut path = vec![Step { id: start, from_redirect: false }];
+    let mut used = HashSet::from([start]);
+    let max_hops = constraints.max_hops.unwrap_or(u32::MAX);
+
+    for (i, stop) in stops.iter().enumerate() {
+        let leg_start = path.last().unwrap().id;
+        // Later waypoints wait their turn, so the legs after can still reach them
+        let allowed = |id: PageId| id == *stop || (!used.contains(&id) && !stops[i..].contains(&id) && constraints.allows(graph, id));
+        let leg = restricted_bfs(graph, leg_start, *stop, &allowed, &HashSet::new(), max_hops - path_length(&path), &mut result);
+        let Some(leg) = leg else {
+            return SearchResult { path: None, searched: result.searched, visited: result.visited, open_set: 0 };
+        };
+        used.extend(leg.iter().map(|step| step.id));
+        path.extend(&leg[1..]);
+    }
+
+    SearchResult { path: Some(path), searched: result.searched, visited: result.visited, open_set: 0 }
+}
+
+/// A shortest path as [`bfs`] finds it, only entering pages that are `allowed`, without following
+/// `banned_links` and of at most `max_length` links. Redirects cost nothing, so pages are searched
+/// in order of distance with a 0-1 BFS rather than a level at a time. Per-page state is kept in
+/// maps, so a search held in by its rules stays cheap, but one that reaches most of the graph,
+/// like a spur search in [`k_shortest_paths`] far from the target, is slower than [`bfs`].
+fn restricted_bfs(
+    graph: &impl LinkGraph,
+    start: PageId,
+    target: PageId,
+    allowed: &dyn Fn(PageId) -> bool,
+    banned_links: &HashSet<(PageId, PageId)>,
+    max_length: u32,
+    result: &mut AlternativePaths,
+) -> Option<Vec<Step>> {
+    let mut distances = HashMap::from([(start, 0u32)]);
+    let mut parents: HashMap<PageId, (PageId, bool)> = HashMap::new();
+    let mut done = HashSet::new();
+    let mut open_set = VecDeque::from([start]);
+
+    while let Some(id) = open_set.pop_front() {
+        if !done.insert(id) {
+            continue;
+        }
+        if id == target {
+            break;
+        }
+        result.searched += 1;
+
+        let distance = distances[&id];
+        let (linked, cost) = if graph.is_redirect(id) {
+            (Either::Left(graph.resolved_target(id).into_iter()), 0)
+        } else {
+            (Either::Right(graph.links(id)), 1)
+        };
+        for link in linked {
+            if distance + cost > max_length || banned_links.contains(&(id, link)) || !allowed(link) {
+                continue;
+            }
+            if distances.get(&link).is_some_and(|known| *known <= distance + cost) {
+                continue;
+            }
+            distances.insert(link, distance + cost);
+            parents.insert(link, (id, cost == 0));
+            if cost == 0 {
+                open_set.push_front(link);
+            } else {
+                open_set.push_back(link);
+            }
+        }
+    }
+    result.visited += distances.len();
+
+    if !done.contains(&target) {
+        return None;
+    }
+    let mut path = vec![target];
+    let mut from_redirect = vec![];
+    while let Some((parent, redirect)) = parents.get(path.last().unwrap()) {
+        from_redirect.push(*redirect);
+        path.push(*parent);
+    }
+    from_redirect.push(false);
+    Some(path.into_iter().zip(from_redirect).rev().map(|(id, from_redirect)| Step { id, from_redirect }).collect())
+}
+
+/// What [`weighted_search`] minimises. Each link costs at least 1, so no metric finds a path
+/// that is cheap because it's long.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Every link costs 1, the same paths [`bfs`] finds
+    Hops,
+    /// A link costs more the more links its page has, `1 + ln(1 + references)`, for paths
+    /// through less obvious pages
+    AvoidHubs,
+    /// A link costs less the more links its page has, down to 1 for the most linked page, for
+    /// paths that go through the hubs
+    PreferHubs,
+}
+
+impl Metric {
+    pub fn parse(name: &str) -> Option<Metric> {
+        match name {
+            "hops" => Some(Metric::Hops),
+            "avoid-hubs" => Some(Metric::AvoidHubs),
+            "prefer-hubs" => Some(Metric::PreferHubs),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Hops => "hops",
+            Metric::AvoidHubs => "avoid-hubs",
+            Metric::PreferHubs => "prefer-hubs",
+        }
+    }
+}
+
+/// The costs of the steps [`weighted_search`] takes
+pub struct Weights {
+    pub metric: Metric,
+    /// Links to each page, the `reference_count_with_redirects` from `count_references`. Only
+    /// needed by the metrics that use them.
+    pub reference_counts: Vec<u32>,
+    /// The cost of following a redirect to its target
+    pub redirect_cost: f64,
+    max_log: f64,
+}
+
+impl Weights {
+    pub fn new(metric: Metric, reference_counts: Vec<u32>, redirect_cost: f64) -> Weights {
+        let max_log = (1.0 + *reference_counts.iter().max().unwrap_or(&0) as f64).ln();
+        Weights { metric, reference_counts, redirect_cost, max_log }
+    }
+
+    /// The cost of a link to `id`, which is the same as to the page it ends up on
+    fn link_cost(&self, graph: &impl LinkGraph, id: PageId) -> f64 {
+        if self.metric == Metric::Hops {
+            return 1.0;
+        }
+        let landing = graph.resolved_target(id).unwrap_or(id);
+        let log = (1.0 + *self.reference_counts.get(landing as usize).unwrap_or(&0) as f64).ln();
+        match self.metric {
+            Metric::Hops => 1.0,
+            Metric::AvoidHubs => 1.0 + log,
+            Metric::PreferHubs => 1.0 + self.max_log - log,
+        }
+    }
+}
+
+/// How many levels of backlinks around the target [`weighted_search`] searches for the A*
+/// heuristic. Further out, pages are only known to be more than this many links away.
+const HEURISTIC_DEPTH: u32 = 2;
+
+/// A page on the open set of [`weighted_search`], ordered so the cheapest comes off a
+/// [`BinaryHeap`] first
+struct Open {
+    priority: f64,
+    id: PageId,
+}
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Open {}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority).then(other.id.cmp(&self.id))
+    }
+}
+
+/// Best-first search from `start` for the cheapest path to `target` under `weights`, returning
+/// the path and its cost. Redirects lead to their resolved target as in [`bfs`], but cost
+/// `weights.redirect_cost`.
+///
+/// With `astar`, pages are taken in order of their cost plus a lower bound on the cost left: the
+/// number of links they are from the target, found for nearby pages by searching backlinks
+/// [`HEURISTIC_DEPTH`] levels out from the target first. The path is just as cheap, the search
+/// just wastes less time heading away from the target.
+pub fn weighted_search(graph: &impl LinkGraph, start: PageId, target: PageId, weights: &Weights, astar: bool) -> (SearchResult, f64) {
+    let start_time = Instant::now();
+
+    let mut near_target = Side::new(if astar { graph.len() } else { 0 });
+    if astar {
+        let mut next = Vec::new();
+        discover_backward(graph, &mut near_target, target, target, false, &mut next);
+        near_target.frontier = next;
+        while near_target.depth < HEURISTIC_DEPTH && !near_target.frontier.is_empty() {
+            near_target.depth += 1;
+            let mut next = Vec::new();
+            for id in std::mem::take(&mut near_target.frontier) {
+                for src in graph.backlinks(id).filter(|src| !graph.is_redirect(*src)) {
+                    discover_backward(graph, &mut near_target, src, id, false, &mut next);
+                }
+            }
+            near_target.frontier = next;
+        }
+    }
+    let remaining = |id: PageId| -> f64 {
+        if !astar {
+            0.0
+        } else if near_target.is_visited(id) {
+            near_target.depths[id as usize] as f64
+        } else {
+            (HEURISTIC_DEPTH + 1) as f64
+        }
+    };
+
+    let mut costs = vec![f64::INFINITY; graph.len()];
+    let mut parents = vec![UNVISITED; graph.len()];
+    let mut from_redirect = vec![false; graph.len()];
+    let mut done = vec![false; graph.len()];
+    costs[start as usize] = 0.0;
+    parents[start as usize] = start;
+    let mut visited: usize = 1;
+    let mut searched: u32 = 0;
+
+    let mut open_set = BinaryHeap::from([Open { priority: remaining(start), id: start }]);
+    while let Some(Open { id, .. }) = open_set.pop() {
+        if done[id as usize] {
+            continue;
+        }
+        done[id as usize] = true;
+        if id == target {
+            break;
+        }
+
+        searched += 1;
+        if searched.is_multiple_of(100_000) {
+            print_progress(searched, start_time, visited, open_set.len());
+        }
+
+        let linked = if graph.is_redirect(id) {
+            Either::Left(graph.resolved_target(id).map(|canonical| (canonical, weights.redirect_cost, true)).into_iter())
+        } else {
+            Either::Right(graph.links(id).map(|link| (link, weights.link_cost(graph, link), false)))
+        };
+        for (link, cost, redirect) in linked {
+            let cost = costs[id as usize] + cost;
+            if done[link as usize] || cost >= costs[link as usize] {
+                continue;
+            }
+            if parents[link as usize] == UNVISITED {
+                visited += 1;
+            }
+            costs[link as usize] = cost;
+            parents[link as usize] = id;
+            from_redirect[link as usize] = redirect;
+            open_set.push(Open { priority: cost + remaining(link), id: link });
+        }
+    }
+
+    let path = done[target as usize].then(|| {
+        let mut path = vec![Step { id: target, from_redirect: from_redirect[target as usize] }];
+        while path.last().unwrap().id != start {
+            let parent = parents[path.last().unwrap().id as usize];
+            path.push(Step { id: parent, from_redirect: from_redirect[parent as usize] });
+        }
+        path.reverse();
+        path[0].from_redirect = false;
+        path
+    });
+
+    (SearchResult { path, searched, visited, open_set: open_set.len() }, costs[target as usize])
+}
+
+pub fn print_progress(searched: u32, start_time: Instant, visited: usize, open_set: usize) {
+    println!(
+        "Pages searched: {} [{:?}/page] | Cache size: {} | Open set size: {}",
+        searched.to_formatted_string(&Locale::en),
+        start_time.elapsed() / searched.max(1),
+        visited.to_formatted_string(&Locale::en),
+        open_set.to_formatted_string(&Locale::en),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::MemoryGraph;
+
+    /// A graph with a page for every title in `links`, the ones in `redirects` being redirects
+    fn graph(redirects: &[&str], links: &[(&str, &str)]) -> MemoryGraph {
+        let mut graph = MemoryGraph::new();
+        for title in redirects {
+            graph.add_page(title, true);
+        }
+        for (src, dst) in links {
+            let src = graph.add_page(src, false);
+            let dst = graph.add_page(dst, false);
+            graph.add_link(src, dst);
+        }
+        graph
+    }
+
+    /// `S` -> `A` | `B` -> `T`, the diamond, and `S` -> `C` -> `D` -> `T` the long way round
+    fn diamond() -> MemoryGraph {
+        graph(&[], &[("S", "A"), ("S", "B"), ("S", "C"), ("A", "T"), ("B", "T"), ("C", "D"), ("D", "T")])
+    }
+
+    /// `A` -> `R` => `B` -> `C` through a redirect, `A` -> `X` -> `Y` -> `C` without, and `A` ->
+    /// `L1` into a loop of redirects `L1` => `L2` => `L1`, which leaves `L2` out of reach
+    fn redirects() -> MemoryGraph {
+        graph(&["R", "L1", "L2"], &[
+            ("A", "R"), ("R", "B"), ("B", "C"), ("A", "X"), ("X", "Y"), ("Y", "C"),
+            ("A", "L1"), ("L1", "L2"), ("L2", "L1"),
+        ])
+    }
+
+    /// A few hundred pages linked at random, a tenth of them redirects, some of those broken or
+    /// looping
+    fn random_graph() -> MemoryGraph {
+        let mut state = 12345u64;
+        let mut next = |below: u32| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as u32 % below
+        };
+
+        let pages = 300;
+        let mut graph = MemoryGraph::new();
+        for id in 0..pages {
+            graph.add_page(&format!("P{}", id), id % 10 == 3);
+        }
+        for id in 0..pages {
+            let links = if graph.is_redirect(id) { 1 } else { next(4) };
+            for _ in 0..links {
+                graph.add_link(id, next(pages));
+            }
+        }
+        graph
+    }
+
+    fn id(graph: &MemoryGraph, title: &str) -> PageId {
+        graph.id(title).unwrap()
+    }
+
+    fn titles(graph: &MemoryGraph, path: &[Step]) -> Vec<String> {
+        path.iter().map(|step| graph.title(step.id)).collect()
+    }
+
+    /// Checks every step of `path` follows a link, or a redirect to its resolved target
+    fn assert_followable(graph: &MemoryGraph, path: &[Step]) {
+        for pair in path.windows(2) {
+            let (from, to) = (pair[0].id, pair[1].id);
+            if pair[1].from_redirect {
+                assert_eq!(graph.resolved_target(from), Some(to), "{:?}", titles(graph, path));
+            } else {
+                assert!(!graph.is_redirect(from) && graph.links(from).any(|link| link == to), "{:?}", titles(graph, path));
+            }
+        }
+    }
+
+    fn bfs_length(graph: &MemoryGraph, start: PageId, target: PageId) -> Option<u32> {
+        bfs(graph, start, Some(target)).path.map(|path| path_length(&path))
+    }
+
+    #[test]
+    fn bfs_follows_redirects_for_free() {
+        let graph = redirects();
+        let path = bfs(&graph, id(&graph, "A"), Some(id(&graph, "C"))).path.unwrap();
+        assert_eq!(titles(&graph, &path), ["A", "R", "B", "C"]);
+        assert_eq!(path.iter().map(|step| step.from_redirect).collect::<Vec<_>>(), [false, false, true, false]);
+        assert_eq!(path_length(&path), 2);
+        assert_followable(&graph, &path);
+    }
+
+    #[test]
+    fn bfs_treats_redirect_loops_as_dead_ends() {
+        let graph = redirects();
+        assert!(bfs(&graph, id(&graph, "A"), Some(id(&graph, "L2"))).path.is_none());
+    }
+
+    #[test]
+    fn bfs_without_target_reaches_everything() {
+        let graph = diamond();
+        let result = bfs(&graph, id(&graph, "S"), None);
+        assert!(result.path.is_none());
+        assert_eq!(result.visited, graph.len());
+    }
+
+    #[test]
+    fn bidirectional_bfs_finds_paths_as_short_as_bfs() {
+        for graph in [diamond(), redirects()] {
+            for start in 0..graph.len() as PageId {
+                for target in 0..graph.len() as PageId {
+                    let path = bidirectional_bfs(&graph, start, target).path;
+                    assert_eq!(path.as_ref().map(|path| path_length(path)), bfs_length(&graph, start, target), "{} -> {}", graph.title(start), graph.title(target));
+                }
+            }
+        }
+
+        let graph = random_graph();
+        for start in (0..graph.len() as PageId).step_by(7) {
+            for target in (0..graph.len() as PageId).step_by(11) {
+                let path = bidirectional_bfs(&graph, start, target).path;
+                assert_eq!(path.as_ref().map(|path| path_length(path)), bfs_length(&graph, start, target), "{} -> {}", start, target);
+                if let Some(path) = path {
+                    assert_eq!((path[0].id, path.last().unwrap().id), (start, target));
+                    assert_followable(&graph, &path);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn all_shortest_paths_counts_every_route() {
+        let graph = diamond();
+        let paths = all_shortest_paths(&graph, id(&graph, "S"), id(&graph, "T"));
+        assert_eq!(paths.length, Some(2));
+        assert_eq!(paths.total(), 2);
+        assert_eq!(paths.paths(10).len(), 2);
+        assert_eq!(paths.paths(1).len(), 1);
+        assert_eq!(paths.unavoidable(), [id(&graph, "S"), id(&graph, "T")]);
+
+        // A redirect to `A` is a third way there at the same length
+        let mut graph = diamond();
+        let redirect = graph.add_page("R", true);
+        graph.add_link(id(&graph, "S"), redirect);
+        graph.add_link(redirect, id(&graph, "A"));
+        let paths = all_shortest_paths(&graph, id(&graph, "S"), id(&graph, "T"));
+        assert_eq!(paths.length, Some(2));
+        assert_eq!(paths.total(), 3);
+        assert_eq!(paths.paths_through(id(&graph, "A")), 2);
+        for path in paths.paths(10) {
+            assert_eq!(path_length(&path), 2);
+            assert_followable(&graph, &path);
+        }
+
+        let graph = redirects();
+        let paths = all_shortest_paths(&graph, id(&graph, "A"), id(&graph, "L2"));
+        assert_eq!((paths.length, paths.total()), (None, 0));
+        assert!(paths.paths(10).is_empty());
+    }
+
+    #[test]
+    fn k_shortest_paths_come_in_order_of_length() {
+        let graph = diamond();
+        let result = k_shortest_paths(&graph, id(&graph, "S"), id(&graph, "T"), 5);
+        assert_eq!(result.paths.iter().map(|path| path_length(path)).collect::<Vec<_>>(), [2, 2, 3]);
+        assert_eq!(titles(&graph, &result.paths[2]), ["S", "C", "D", "T"]);
+        assert!(k_shortest_paths(&graph, id(&graph, "S"), id(&graph, "T"), 0).paths.is_empty());
+
+        let graph = redirects();
+        let result = k_shortest_paths(&graph, id(&graph, "A"), id(&graph, "C"), 5);
+        assert_eq!(result.paths.len(), 2);
+        assert_eq!(titles(&graph, &result.paths[0]), ["A", "R", "B", "C"]);
+        assert_eq!(titles(&graph, &result.paths[1]), ["A", "X", "Y", "C"]);
+
+        let graph = random_graph();
+        for (start, target) in [(0, 150), (20, 280), (111, 7)] {
+            let result = k_shortest_paths(&graph, start, target, 8);
+            let lengths: Vec<u32> = result.paths.iter().map(|path| path_length(path)).collect();
+            assert!(lengths.is_sorted());
+            assert_eq!(lengths.first().copied(), bfs_length(&graph, start, target));
+            for (i, path) in result.paths.iter().enumerate() {
+                assert_followable(&graph, path);
+                assert!(!result.paths[..i].contains(path));
+                let pages: HashSet<PageId> = path.iter().map(|step| step.id).collect();
+                assert_eq!(pages.len(), path.len(), "loop in {:?}", titles(&graph, path));
+            }
+        }
+    }
+
+    #[test]
+    fn disjoint_paths_reroute_instead_of_blocking() {
+        // Taking the shortest path S-A-B-T first leaves no second path avoiding it, but
+        // S-A-D-E-T and S-C-B-T share nothing
+        let graph = graph(&[], &[("S", "A"), ("A", "B"), ("B", "T"), ("S", "C"), ("C", "B"), ("A", "D"), ("D", "E"), ("E", "T")]);
+        let result = disjoint_paths(&graph, id(&graph, "S"), id(&graph, "T"), 2);
+        assert_eq!(result.paths.len(), 2);
+        assert_eq!(titles(&graph, &result.paths[0]), ["S", "C", "B", "T"]);
+        assert_eq!(titles(&graph, &result.paths[1]), ["S", "A", "D", "E", "T"]);
+
+        assert_eq!(disjoint_paths(&graph, id(&graph, "S"), id(&graph, "T"), 5).paths.len(), 2);
+        assert_eq!(disjoint_paths(&graph, id(&graph, "S"), id(&graph, "T"), 1).paths.len(), 1);
+        assert!(disjoint_paths(&graph, id(&graph, "S"), id(&graph, "T"), 0).paths.is_empty());
+    }
+
+    #[test]
+    fn disjoint_paths_follow_redirects_for_free() {
+        let graph = redirects();
+        let result = disjoint_paths(&graph, id(&graph, "A"), id(&graph, "C"), 3);
+        assert_eq!(result.paths.len(), 2);
+        assert_eq!(titles(&graph, &result.paths[0]), ["A", "R", "B", "C"]);
+        assert_eq!(result.paths[0][2], Step { id: id(&graph, "B"), from_redirect: true });
+        assert_eq!(path_length(&result.paths[0]), 2);
+        assert!(disjoint_paths(&graph, id(&graph, "A"), id(&graph, "L2"), 3).paths.is_empty());
+
+        let graph = random_graph();
+        for (start, target) in [(0, 150), (20, 280), (111, 7)] {
+            let result = disjoint_paths(&graph, start, target, 10);
+            assert_eq!(result.paths.first().map(|path| path_length(path)), bfs_length(&graph, start, target));
+            let mut seen = HashSet::new();
+            for path in &result.paths {
+                assert_followable(&graph, path);
+                assert_eq!((path[0].id, path.last().unwrap().id), (start, target));
+                for step in &path[1..path.len() - 1] {
+                    assert!(seen.insert(step.id), "{} is on two paths", graph.title(step.id));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn constrained_search_follows_the_rules() {
+        let graph = diamond();
+        let (s, t) = (id(&graph, "S"), id(&graph, "T"));
+        let search = |constraints: Constraints| constrained_search(&graph, s, t, &constraints).path.map(|path| titles(&graph, &path));
+
+        let excluded = HashSet::from([id(&graph, "A"), id(&graph, "B")]);
+        assert_eq!(search(Constraints { excluded: excluded.clone(), ..Default::default() }).unwrap(), ["S", "C", "D", "T"]);
+        assert_eq!(search(Constraints { excluded, max_hops: Some(2), ..Default::default() }), None);
+        let patterns = vec![Regex::new("^[AB]$").unwrap()];
+        assert_eq!(search(Constraints { excluded_patterns: patterns, ..Default::default() }).unwrap(), ["S", "C", "D", "T"]);
+        assert_eq!(search(Constraints { waypoints: vec![id(&graph, "D")], ..Default::default() }).unwrap(), ["S", "C", "D", "T"]);
+        assert_eq!(search(Constraints { max_hops: Some(1), ..Default::default() }), None);
+
+        let graph = redirects();
+        let constraints = Constraints { no_redirects: true, ..Default::default() };
+        let path = constrained_search(&graph, id(&graph, "A"), id(&graph, "C"), &constraints).path.unwrap();
+        assert_eq!(titles(&graph, &path), ["A", "X", "Y", "C"]);
+    }
+
+    #[test]
+    fn weighted_search_prices_links_by_metric() {
+        let graph = diamond();
+        let (s, t) = (id(&graph, "S"), id(&graph, "T"));
+        let mut counts = vec![0; graph.len()];
+        counts[id(&graph, "A") as usize] = 100;
+
+        for astar in [false, true] {
+            let weights = Weights::new(Metric::Hops, Vec::new(), 0.0);
+            let (result, cost) = weighted_search(&graph, s, t, &weights, astar);
+            assert_eq!((path_length(&result.path.unwrap()), cost), (2, 2.0));
+
+            let weights = Weights::new(Metric::AvoidHubs, counts.clone(), 0.0);
+            let (result, cost) = weighted_search(&graph, s, t, &weights, astar);
+            assert_eq!(titles(&graph, &result.path.unwrap()), ["S", "B", "T"]);
+            assert_eq!(cost, 2.0);
+
+            let weights = Weights::new(Metric::PreferHubs, counts.clone(), 0.0);
+            let (result, _) = weighted_search(&graph, s, t, &weights, astar);
+            assert_eq!(titles(&graph, &result.path.unwrap()), ["S", "A", "T"]);
+        }
+    }
+
+    #[test]
+    fn weighted_search_charges_for_redirects() {
+        let graph = redirects();
+        let (a, c) = (id(&graph, "A"), id(&graph, "C"));
+        for astar in [false, true] {
+            let (result, cost) = weighted_search(&graph, a, c, &Weights::new(Metric::Hops, Vec::new(), 0.5), astar);
+            assert_eq!(titles(&graph, &result.path.unwrap()), ["A", "R", "B", "C"]);
+            assert_eq!(cost, 2.5);
+
+            let (result, cost) = weighted_search(&graph, a, c, &Weights::new(Metric::Hops, Vec::new(), 2.0), astar);
+            assert_eq!(titles(&graph, &result.path.unwrap()), ["A", "X", "Y", "C"]);
+            assert_eq!(cost, 3.0);
+
+            let (result, cost) = weighted_search(&graph, a, id(&graph, "L2"), &Weights::new(Metric::Hops, Vec::new(), 0.0), astar);
+            assert!(result.path.is_none() && cost.is_infinite());
+        }
+
+        let graph = random_graph();
+        let weights = Weights::new(Metric::Hops, Vec::new(), 0.0);
+        for (start, target) in [(0, 150), (20, 280), (111, 7)] {
+            let (result, cost) = weighted_search(&graph, start, target, &weights, true);
+            assert_eq!(result.path.map(|path| path_length(&path)), bfs_length(&graph, start, target));
+            assert!(cost.is_infinite() || cost == bfs_length(&graph, start, target).unwrap() as f64);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use unicode_normalization::UnicodeNormalization;
+use crate::namespaces::Namespaces;
+
+/// Characters MediaWiki treats as a space in titles. Runs of them collapse into one space.
+fn is_title_whitespace(c: char) -> bool {
+    matches!(c,
+        ' ' | '_' | '\u{a0}' | '\u{1680}' | '\u{180e}' | '\u{2000}'..='\u{200a}' |
+        '\u{2028}' | '\u{2029}' | '\u{202f}' | '\u{205f}' | '\u{3000}'
+    )
+}
+
+/// Invisible direction marks MediaWiki strips from titles
+fn is_direction_mark(c: char) -> bool {
+    matches!(c, '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}')
+}
+
+/// Normalizes a title the way MediaWiki does before storing or looking it up:
+/// - Unicode NFC
+/// - underscores and other spaces become a single space, and the title is trimmed
+/// - a leading `:` is dropped
+/// - a namespace prefix is replaced with the namespace's canonical name
+/// - the first letter is uppercased unless the namespace is case-sensitive
+///
+/// e.g. `" united_kingdom "` -> `"United kingdom"`, `"category:  towns"` -> `"Category:Towns"`
+pub fn normalize(title: &str, namespaces: &Namespaces) -> String {
+    let mut collapsed = String::with_capacity(title.len());
+    let mut pending_space = false;
+    for c in title.nfc() {
+        if is_direction_mark(c) {
+            continue;
+        }
+
+        if is_title_whitespace(c) {
+            pending_space = true;
+            continue;
+        }
+
+        if pending_space && !collapsed.is_empty() {
+            collapsed.push(' ');
+        }
+        pending_space = false;
+        collapsed.push(c);
+    }
+
+    let title = collapsed.strip_prefix(':').map(|t| t.trim_start()).unwrap_or(&collapsed);
+
+    let (namespace, name) = match title.split_once(':') {
+        Some((prefix, name)) => match namespaces.id(prefix) {
+            Some(id) => (id, name.trim_start()),
+            None => (0, title)
+        },
+        None => (0, title)
+    };
+
+    let mut normalized = String::with_capacity(title.len());
+    let first_letter = match namespaces.get(namespace) {
+        Some(ns) => {
+            if !ns.name.is_empty() {
+                normalized.push_str(&ns.name);
+                normalized.push(':');
+            }
+            ns.first_letter
+        }
+        None => true
+    };
+
+    let mut chars = name.chars();
+    if let Some(first) = chars.next() {
+        let mut upper = first.to_uppercase();
+        match (first_letter, upper.len()) {
+            (true, 1) => normalized.push(upper.next().unwrap()),
+            _ => normalized.push(first)
+        }
+        normalized.extend(chars);
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enwiki(title: &str) -> String {
+        normalize(title, &Namespaces::enwiki())
+    }
+
+    #[test]
+    fn underscores_and_spaces_collapse() {
+        assert_eq!(enwiki(" united_kingdom "), "United kingdom");
+        assert_eq!(enwiki("New__York _ City"), "New York City");
+        assert_eq!(enwiki("Foo\u{a0}\u{3000}bar"), "Foo bar");
+        assert_eq!(enwiki("Foo\u{200e}bar"), "Foobar");
+    }
+
+    #[test]
+    fn namespaces_get_their_canonical_name() {
+        assert_eq!(enwiki("category:  towns"), "Category:Towns");
+        assert_eq!(enwiki("image:Foo.jpg"), "File:Foo.jpg");
+        assert_eq!(enwiki("WP:npov"), "Wikipedia:Npov");
+        assert_eq!(enwiki(":category_talk:x"), "Category talk:X");
+        assert_eq!(enwiki("Star Wars: a new hope"), "Star Wars: a new hope");
+    }
+
+    #[test]
+    fn first_letter_is_uppercased_where_the_namespace_says() {
+        assert_eq!(enwiki("éclair"), "Éclair");
+        // 'ß' uppercases to two letters, which MediaWiki doesn't do
+        assert_eq!(enwiki("ßtraße"), "ßtraße");
+        assert_eq!(enwiki("iPhone"), "IPhone");
+
+        let namespaces = Namespaces::from_siteinfo(
+            "<namespace key=\"0\" case=\"first-letter\" />\n<namespace key=\"1198\" case=\"case-sensitive\">Translations</namespace>",
+        );
+        assert_eq!(normalize("translations:iPhone", &namespaces), "Translations:iPhone");
+        assert_eq!(normalize("iPhone", &namespaces), "IPhone");
+    }
+
+    #[test]
+    fn titles_are_composed_to_nfc() {
+        assert_eq!(enwiki("e\u{301}cole"), "École");
+        assert_eq!(enwiki("Cafe\u{301}"), "Caf\u{e9}");
+        assert_eq!(enwiki("Cafe\u{301}"), enwiki("Café"));
+    }
+}
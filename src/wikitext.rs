@@ -0,0 +1,430 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+/// Strips `prefix` from the start of `text`, comparing characters case-insensitively
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let mut text_chars = text.char_indices();
+    for p in prefix.chars() {
+        let (_, t) = text_chars.next()?;
+        if !t.to_lowercase().eq(p.to_lowercase()) {
+            return None;
+        }
+    }
+
+    Some(text_chars.next().map(|(i, _)| &text[i..]).unwrap_or(""))
+}
+
+pub enum Redirect<'a> {
+    Target(&'a str),
+    /// The body starts like a redirect but its `[[` is never closed
+    Unterminated,
+}
+
+/// Checks whether `body` is a redirect the way MediaWiki does: optional leading whitespace, one
+/// of `magic_words` in any case, an optional `:` and then a `[[link]]`. Anything after a `|` in
+/// the link is ignored.
+///
+/// e.g. `#REDIRECT [[Foo]]`, `  #redirect[[Foo]]`, `#Redirect: [[Foo|bar]]`, `#WEITERLEITUNG [[Foo]]`
+pub fn parse_redirect<'a>(body: &'a str, magic_words: &[String]) -> Option<Redirect<'a>> {
+    let body = body.trim_start();
+    let after_word = magic_words.iter().find_map(|word| strip_prefix_ignore_case(body, word))?;
+
+    let after_word = after_word.trim_start();
+    let after_word = after_word.strip_prefix(':').unwrap_or(after_word).trim_start();
+    let link = after_word.strip_prefix("[[")?;
+
+    let Some(end) = link.find("]]") else { return Some(Redirect::Unterminated); };
+    let target = &link[..end];
+    Some(Redirect::Target(target.split('|').next().unwrap().trim()))
+}
+
+/// Tags whose contents are never rendered as links
+const HIDDEN_TAGS: [&str; 7] = ["nowiki", "pre", "code", "math", "chem", "syntaxhighlight", "source"];
+const REF_TAG: &str = "ref";
+
+/// If `text` starts with an opening tag named `name` (e.g. `<ref name="x">`), returns the length
+/// of that tag and whether it is self-closing
+fn opening_tag(text: &str, name: &str) -> Option<(usize, bool)> {
+    let after_name = strip_prefix_ignore_case(text.strip_prefix('<')?, name)?;
+    if !after_name.starts_with(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/') {
+        return None;
+    }
+
+    let end = after_name.find('>')?;
+    let tag_len = text.len() - after_name.len() + end + 1;
+    Some((tag_len, after_name[..end].ends_with('/')))
+}
+
+/// Finds the end of the first `</name>` in `text`, ignoring case and whitespace before the `>`
+fn closing_tag_end(text: &str, name: &str) -> Option<usize> {
+    let mut searched = 0;
+    while let Some(start) = text[searched..].find("</") {
+        let after_slash = &text[searched + start + 2..];
+        if let Some(after_name) = strip_prefix_ignore_case(after_slash, name) {
+            let after_space = after_name.trim_start();
+            if after_space.starts_with('>') {
+                return Some(text.len() - after_space.len() + 1);
+            }
+        }
+        searched += start + 2;
+    }
+    None
+}
+
+/// Masks everything a reader can't click on before links are extracted: `<!-- comments -->`
+/// and the contents of `<nowiki>`, `<pre>`, `<code>`, `<math>`, `<chem>`, `<syntaxhighlight>`,
+/// `<source>` and, unless `include_refs`, `<ref>`. Comments are removed and tags are replaced
+/// with a space. An unclosed comment hides the rest of the page, like in MediaWiki, while an
+/// unclosed tag is left as it is.
+pub fn mask_hidden(body: &str, include_refs: bool) -> Cow<'_, str> {
+    if !body.contains('<') {
+        return Cow::Borrowed(body);
+    }
+
+    let mut masked = String::with_capacity(body.len());
+    let mut rest = body;
+    'search: while let Some(start) = rest.find('<') {
+        masked.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = match comment.find("-->") {
+                Some(end) => &comment[end + "-->".len()..],
+                None => ""
+            };
+            continue;
+        }
+
+        let refs = if include_refs { None } else { Some(REF_TAG) };
+        for name in HIDDEN_TAGS.into_iter().chain(refs) {
+            let Some((tag_len, self_closing)) = opening_tag(rest, name) else { continue; };
+            if self_closing {
+                rest = &rest[tag_len..];
+                continue 'search;
+            }
+
+            if let Some(end) = closing_tag_end(&rest[tag_len..], name) {
+                masked.push(' ');
+                rest = &rest[tag_len + end..];
+                continue 'search;
+            }
+        }
+
+        masked.push('<');
+        rest = &rest[1..];
+    }
+    masked.push_str(rest);
+
+    Cow::Owned(masked)
+}
+
+/// Returns the part of a template's wikitext that appears where it is transcluded: only the
+/// `<onlyinclude>` sections if there are any, otherwise everything outside `<noinclude>`.
+/// `<includeonly>` tags are dropped but their contents kept.
+pub fn transcluded_content(template: &str) -> String {
+    const ONLYINCLUDE: &str = "onlyinclude";
+    const NOINCLUDE: &str = "noinclude";
+    const INCLUDEONLY: &str = "includeonly";
+
+    let mut content = String::with_capacity(template.len());
+    let mut rest = template;
+
+    let only_include = rest.contains("<onlyinclude>");
+    while let Some(start) = rest.find('<') {
+        let tag = &rest[start..];
+
+        if only_include {
+            let Some((tag_len, _)) = opening_tag(tag, ONLYINCLUDE) else {
+                rest = &rest[start + 1..];
+                continue;
+            };
+            let inner = &tag[tag_len..];
+            let end = closing_tag_end(inner, ONLYINCLUDE).unwrap_or(inner.len());
+            let inner_end = inner[..end].rfind("</").unwrap_or(end);
+            content.push_str(&inner[..inner_end]);
+            rest = &inner[end..];
+            continue;
+        }
+
+        content.push_str(&rest[..start]);
+        if let Some((tag_len, self_closing)) = opening_tag(tag, NOINCLUDE) {
+            let inner = &tag[tag_len..];
+            rest = match (self_closing, closing_tag_end(inner, NOINCLUDE)) {
+                (true, _) => inner,
+                (false, Some(end)) => &inner[end..],
+                (false, None) => ""
+            };
+        }
+        else if let Some((tag_len, _)) = opening_tag(tag, INCLUDEONLY) {
+            rest = &tag[tag_len..];
+        }
+        else if let Some(end) = strip_prefix_ignore_case(tag, "</includeonly>") {
+            rest = end;
+        }
+        else {
+            content.push('<');
+            rest = &tag[1..];
+        }
+    }
+
+    if !only_include {
+        content.push_str(rest);
+    }
+    content
+}
+
+/// A `{{template|arg|...}}` in wikitext
+pub struct Transclusion<'a> {
+    /// Byte offset of the opening `{{`
+    pub position: usize,
+    pub name: &'a str,
+    pub args: Vec<&'a str>,
+}
+
+/// Finds every template transclusion in `body`, including ones nested inside the arguments of
+/// another. Template parameters (`{{{1}}}`) and parser functions (`{{#if:...}}`) are skipped.
+pub fn transclusions(body: &str) -> Vec<Transclusion<'_>> {
+    let bytes = body.as_bytes();
+    let mut found = Vec::new();
+
+    for (position, _) in body.match_indices("{{") {
+        if bytes.get(position + 2) == Some(&b'{') || (position > 0 && bytes[position - 1] == b'{') {
+            continue;
+        }
+
+        // Split the arguments on top-level '|' until the matching '}}'
+        let inner_start = position + 2;
+        let mut depth = 0;
+        let mut segments = Vec::new();
+        let mut segment_start = inner_start;
+        let mut end = None;
+        let mut i = inner_start;
+        while i < bytes.len() {
+            match (bytes[i], bytes.get(i + 1)) {
+                (b'{', Some(b'{')) | (b'[', Some(b'[')) => { depth += 1; i += 2; continue; }
+                (b'}', Some(b'}')) if depth == 0 => { end = Some(i); break; }
+                (b'}', Some(b'}')) | (b']', Some(b']')) if depth > 0 => { depth -= 1; i += 2; continue; }
+                (b'|', _) if depth == 0 => {
+                    segments.push(&body[segment_start..i]);
+                    segment_start = i + 1;
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+
+        let Some(end) = end else { continue; };
+        segments.push(&body[segment_start..end]);
+
+        let name = segments[0].trim();
+        if name.is_empty() || name.starts_with('#') || name.contains(['{', '}', '[', ']', '<']) {
+            continue;
+        }
+
+        found.push(Transclusion { position, name, args: segments[1..].to_vec() });
+    }
+
+    found
+}
+
+/// Which sections of a page links are taken from. Headings are compared after
+/// [`normalize_heading`], and a listed heading covers all of its subsections. The lead section
+/// before the first heading is always kept.
+pub enum SectionPolicy {
+    /// Keep only the listed sections
+    Include(Vec<String>),
+    /// Keep everything except the listed sections
+    Exclude(Vec<String>),
+}
+
+impl SectionPolicy {
+    pub fn include(headings: &[&str]) -> SectionPolicy {
+        SectionPolicy::Include(headings.iter().map(|h| normalize_heading(h)).collect())
+    }
+
+    pub fn exclude(headings: &[&str]) -> SectionPolicy {
+        SectionPolicy::Exclude(headings.iter().map(|h| normalize_heading(h)).collect())
+    }
+
+    fn headings(&self) -> &[String] {
+        match self {
+            SectionPolicy::Include(headings) | SectionPolicy::Exclude(headings) => headings
+        }
+    }
+
+    /// e.g. `exclude: see also, references`
+    pub fn describe(&self) -> String {
+        let mode = match self {
+            SectionPolicy::Include(_) => "include",
+            SectionPolicy::Exclude(_) => "exclude"
+        };
+        format!("{}: {}", mode, self.headings().join(", "))
+    }
+
+    /// Returns the byte ranges of `body` in sections the policy keeps
+    pub fn kept_ranges(&self, body: &str) -> Vec<Range<usize>> {
+        let keep_unlisted = matches!(self, SectionPolicy::Exclude(_));
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+
+        // The level and decision of the listed heading we are inside of, if any
+        let mut listed: Option<(usize, bool)> = None;
+        let mut keeping = true;
+        let mut section_start = 0;
+        let mut offset = 0;
+        for line in body.split_inclusive('\n') {
+            if let Some((level, heading)) = parse_heading(line) {
+                if keeping && section_start < offset {
+                    ranges.push(section_start..offset);
+                }
+
+                if listed.is_some_and(|(listed_level, _)| level <= listed_level) {
+                    listed = None;
+                }
+                if listed.is_none() && self.headings().contains(&normalize_heading(heading)) {
+                    listed = Some((level, !keep_unlisted));
+                }
+
+                keeping = listed.map(|(_, keep)| keep).unwrap_or(keep_unlisted);
+                section_start = offset + line.len();
+            }
+            offset += line.len();
+        }
+
+        if keeping && section_start < body.len() {
+            ranges.push(section_start..body.len());
+        }
+        ranges
+    }
+}
+
+/// Lowercases a heading and collapses whitespace and underscores, so `== See_also ==` and
+/// `===See Also===` compare equal
+pub fn normalize_heading(heading: &str) -> String {
+    heading.split(|c: char| c.is_whitespace() || c == '_')
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Parses a heading line like `== See also ==` into its level and text. Unbalanced `=` count
+/// towards the text, as in MediaWiki.
+fn parse_heading(line: &str) -> Option<(usize, &str)> {
+    let line = line.trim_end();
+    let opening = line.len() - line.trim_start_matches('=').len();
+    let closing = line.len() - line.trim_end_matches('=').len();
+    if opening == 0 || closing == 0 || opening == line.len() {
+        return None;
+    }
+
+    let level = opening.min(closing).min(6);
+    let heading = line[level..line.len() - level].trim();
+    if heading.is_empty() {
+        return None;
+    }
+    Some((level, heading))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redirect(body: &str) -> Option<&str> {
+        let magic_words = ["#REDIRECT".to_string(), "#WEITERLEITUNG".to_string()];
+        match parse_redirect(body, &magic_words)? {
+            Redirect::Target(target) => Some(target),
+            Redirect::Unterminated => Some("<unterminated>")
+        }
+    }
+
+    #[test]
+    fn redirects_in_any_case_and_spacing() {
+        assert_eq!(redirect("#REDIRECT [[Foo]]"), Some("Foo"));
+        assert_eq!(redirect("#redirect[[Foo]]"), Some("Foo"));
+        assert_eq!(redirect("#ReDiReCt [[Foo]]"), Some("Foo"));
+        assert_eq!(redirect("  \n#REDIRECT   [[ Foo bar ]] {{R from move}}"), Some("Foo bar"));
+        assert_eq!(redirect("#WEITERLEITUNG [[Foo]]"), Some("Foo"));
+        assert_eq!(redirect("#REDIRECT [[Foo|the foo]]"), Some("Foo"));
+        assert_eq!(redirect("#REDIRECT [[Foo#History]]"), Some("Foo#History"));
+    }
+
+    #[test]
+    fn redirects_with_a_colon() {
+        assert_eq!(redirect("#REDIRECT: [[Foo]]"), Some("Foo"));
+        assert_eq!(redirect("#REDIRECT:[[Foo]]"), Some("Foo"));
+        assert_eq!(redirect("#REDIRECT : [[Foo]]"), Some("Foo"));
+        assert_eq!(redirect("#REDIRECT [[:Category:Foo]]"), Some(":Category:Foo"));
+        assert_eq!(redirect("#REDIRECT:: [[Foo]]"), None);
+    }
+
+    #[test]
+    fn pages_that_are_not_redirects() {
+        assert_eq!(redirect("A page about #REDIRECT [[Foo]]"), None);
+        assert_eq!(redirect("#REDIRECTION [[Foo]]"), None);
+        assert_eq!(redirect("#REDIRECT Foo"), None);
+        assert_eq!(redirect("#REDIRECT [[Foo"), Some("<unterminated>"));
+        assert_eq!(redirect(""), None);
+    }
+
+    #[test]
+    fn hidden_text_is_masked() {
+        assert_eq!(mask_hidden("[[A]]<!-- [[B]] -->[[C]]", false), "[[A]][[C]]");
+        assert_eq!(mask_hidden("[[A]]<!-- [[B]]", false), "[[A]]");
+        assert_eq!(mask_hidden("<nowiki>[[A]]</nowiki>[[B]]", false), " [[B]]");
+        assert_eq!(mask_hidden("<PRE class=\"x\">[[A]]</pre >[[B]]", false), " [[B]]");
+        assert_eq!(mask_hidden("<math>[[A]]", false), "<math>[[A]]");
+        assert_eq!(mask_hidden("<nowiki/>[[A]]", false), "[[A]]");
+        assert_eq!(mask_hidden("<code>x</code><preface>[[A]]", false), " <preface>[[A]]");
+        assert!(matches!(mask_hidden("[[A]] and [[B]]", false), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn refs_are_masked_unless_included() {
+        let body = "[[A]]<ref name=\"x\">[[B]]</ref><ref name=\"y\" />[[C]]";
+        assert_eq!(mask_hidden(body, false), "[[A]] [[C]]");
+        assert_eq!(mask_hidden(body, true), body);
+    }
+
+    const PAGE: &str = "Lead [[A]]\n== History ==\n[[B]]\n=== Early_history ===\n[[C]]\n== See also ==\n[[D]]\n==References==\n[[E]]\n";
+
+    fn kept(policy: &SectionPolicy) -> String {
+        policy.kept_ranges(PAGE).into_iter().map(|range| &PAGE[range]).collect()
+    }
+
+    #[test]
+    fn sections_can_be_excluded() {
+        let policy = SectionPolicy::exclude(&["see_also", "References"]);
+        assert_eq!(kept(&policy), "Lead [[A]]\n[[B]]\n[[C]]\n");
+        assert_eq!(policy.describe(), "exclude: see also, references");
+
+        // A listed heading covers its subsections
+        assert_eq!(kept(&SectionPolicy::exclude(&["history"])), "Lead [[A]]\n[[D]]\n[[E]]\n");
+    }
+
+    #[test]
+    fn sections_can_be_included() {
+        assert_eq!(kept(&SectionPolicy::include(&["History"])), "Lead [[A]]\n[[B]]\n[[C]]\n");
+        assert_eq!(kept(&SectionPolicy::include(&["Early history"])), "Lead [[A]]\n[[C]]\n");
+        assert_eq!(kept(&SectionPolicy::include(&[])), "Lead [[A]]\n");
+    }
+
+    #[test]
+    fn headings_need_equals_on_both_sides() {
+        assert_eq!(parse_heading("== See also ==\n"), Some((2, "See also")));
+        assert_eq!(parse_heading("=== Uneven =="), Some((2, "= Uneven")));
+        assert_eq!(parse_heading("== Not a heading"), None);
+        assert_eq!(parse_heading("===="), None);
+    }
+
+    #[test]
+    fn transclusions_and_their_arguments() {
+        let body = "{{Infobox|name=[[A|a]]|{{Flag|UK}}}} {{{1}}} {{#if:x|{{Y}}}} {{ Cite web |url=x}} {{Unclosed";
+        let found = transclusions(body);
+        let names: Vec<&str> = found.iter().map(|t| t.name).collect();
+        assert_eq!(names, ["Infobox", "Flag", "Y", "Cite web"]);
+        assert_eq!(found[0].args, ["name=[[A|a]]", "{{Flag|UK}}"]);
+        assert_eq!(found[0].position, 0);
+        assert_eq!(found[1].args, ["UK"]);
+        assert_eq!(found[3].args, ["url=x"]);
+    }
+}